@@ -0,0 +1,66 @@
+//! Aliases for commands, defined in the `[alias]` table of the configuration
+//! file.
+//!
+//! ```toml
+//! [alias]
+//! t = "tags --group --limit 100"
+//! ```
+//!
+//! The alias is replaced by its command line before parsing the arguments,
+//! so `hdcquery t redis` is the same as `hdcquery tags --group --limit 100
+//! redis`. Aliases can't replace built-in commands.
+
+use crate::options;
+
+/// Check that `name` can be used for an alias.
+pub fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty() || name.starts_with('-') || name.contains(char::is_whitespace) {
+        anyhow::bail!("invalid alias name {:?}", name);
+    }
+
+    if options::builtin_commands().contains(&name) {
+        anyhow::bail!("alias {:?} can't replace a built-in command", name);
+    }
+
+    Ok(())
+}
+
+/// Replace the command in `args` if it is an alias.
+pub fn expand(mut args: Vec<String>, aliases: &[(&str, &str)]) -> anyhow::Result<Vec<String>> {
+    let index = match options::command_index(&args) {
+        Some(index) => index,
+        None => return Ok(args),
+    };
+
+    let name = args[index].as_str();
+    if let Some((_, command)) = aliases.iter().find(|(n, _)| *n == name) {
+        let words = shell_words::split(command)
+            .map_err(|e| anyhow::anyhow!("invalid alias {:?}: {}", name, e))?;
+
+        args.splice(index..=index, words);
+    }
+
+    Ok(args)
+}
+
+#[test]
+fn expand_aliases() {
+    let aliases = [("t", "tags --limit 100 -g"), ("s", "search")];
+    let expand = |args: &[&str]| {
+        let args = args.iter().map(|s| s.to_string()).collect();
+        expand(args, &aliases).unwrap()
+    };
+
+    assert_eq!(
+        expand(&["--no-pager", "t", "redis"]),
+        ["--no-pager", "tags", "--limit", "100", "-g", "redis"]
+    );
+
+    assert_eq!(expand(&["--replay", "t", "s"]), ["--replay", "t", "search"]);
+    assert_eq!(expand(&["show", "t"]), ["show", "t"]);
+    assert_eq!(expand(&["--", "t"]), ["--", "t"]);
+
+    assert!(check_name("tags").is_err());
+    assert!(check_name("mock-server").is_err());
+    assert!(check_name("tg").is_ok());
+}
@@ -0,0 +1,503 @@
+//! Backends to send requests to the APIs.
+//!
+//! Commands use a `Backend` instead of an HTTP client, so the responses can
+//! be read from other sources. `Http` sends the requests to the network,
+//! `Recorder` saves the responses of another backend in a directory, and
+//! `Fixtures` reads them back (with `--record` and `--replay`). `Cache`
+//! reuses recent responses (with `--cache-ttl`). `Rebase` sends the requests
+//! for Docker Hub to another server, like the one started by `hdcquery
+//! mock-server`.
+//!
+//! The response for a URL is stored in `{host}/{path}@{query}.json`. The
+//! request, the status and the relevant response headers are stored in a
+//! `.headers` file next to it:
+//!
+//! ```text
+//! > GET https://ghcr.io/v2/foo/tags/list
+//! > Authorization: Bearer [redacted]
+//! < HTTP 404
+//! < Link: </v2/foo/tags/list?last=bar>; rel="next"
+//! ```
+//!
+//! Credentials are redacted, like in `--trace-http`, so the files can be
+//! attached to a bug report.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+
+use crate::trace;
+
+/// Response headers stored by `Recorder`.
+const RECORDED_HEADERS: &[&str] = &[
+    "content-type",
+    "docker-content-digest",
+    "link",
+    "retry-after",
+    "www-authenticate",
+];
+
+/// Prefix of the URLs replaced by `Rebase`.
+pub const DOCKER_HUB_URL: &str = "https://hub.docker.com";
+
+/// Fixtures used in the tests.
+#[cfg(test)]
+pub const TEST_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+#[async_trait::async_trait]
+pub trait Backend: Send + Sync {
+    /// Send a GET request to `url`.
+    ///
+    /// Responses with error status codes are not errors. Use
+    /// `Response::error_for_status` to check them.
+    async fn get(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<Response>;
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Value of the header `name`, case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn error_for_status(self) -> anyhow::Result<Self> {
+        if self.status >= 400 {
+            anyhow::bail!("HTTP status {} for URL ({})", self.status, self.url);
+        }
+
+        Ok(self)
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+/// Send requests to the network.
+pub struct Http {
+    client: reqwest::Client,
+}
+
+impl Http {
+    pub fn new(client: reqwest::Client) -> Http {
+        Http { client }
+    }
+}
+
+#[async_trait::async_trait]
+impl Backend for Http {
+    async fn get(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<Response> {
+        let mut request = self.client.get(url);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let response = request.send().await?;
+
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(n, v)| Some((n.as_str().to_string(), v.to_str().ok()?.to_string())))
+            .collect();
+
+        Ok(Response {
+            url: response.url().to_string(),
+            status: response.status().as_u16(),
+            headers,
+            body: response.bytes().await?.to_vec(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<B: Backend + ?Sized> Backend for std::sync::Arc<B> {
+    async fn get(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<Response> {
+        (**self).get(url, headers).await
+    }
+}
+
+/// Send requests for Docker Hub to another server (with `--hub-url`).
+///
+/// URLs for other hosts are not modified.
+pub struct Rebase<B> {
+    backend: B,
+    base: String,
+}
+
+impl<B: Backend> Rebase<B> {
+    pub fn new(backend: B, base: &str) -> Rebase<B> {
+        Rebase {
+            backend,
+            base: base.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<B: Backend> Backend for Rebase<B> {
+    async fn get(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<Response> {
+        match url.strip_prefix(DOCKER_HUB_URL) {
+            Some(path) if path.starts_with('/') => {
+                let url = format!("{}{}", self.base, path);
+                self.backend.get(&url, headers).await
+            }
+
+            _ => self.backend.get(url, headers).await,
+        }
+    }
+}
+
+/// Send requests to another backend, and save the responses in a directory.
+pub struct Recorder<B> {
+    backend: B,
+    dir: PathBuf,
+}
+
+impl<B: Backend> Recorder<B> {
+    pub fn new<P: Into<PathBuf>>(backend: B, dir: P) -> Recorder<B> {
+        Recorder {
+            backend,
+            dir: dir.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<B: Backend> Backend for Recorder<B> {
+    async fn get(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<Response> {
+        let response = self.backend.get(url, headers).await?;
+
+        let path = &fixture_path(&self.dir, url)?;
+        write_fixture(path, url, headers, &response)?;
+
+        Ok(response)
+    }
+}
+
+/// Send requests to another backend, and reuse the responses saved in a
+/// directory if they are newer than `ttl`.
+///
+/// Only successful responses are saved. Requests with credentials, and
+/// responses with tokens, are never cached.
+pub struct Cache<B> {
+    backend: B,
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl<B: Backend> Cache<B> {
+    pub fn new<P: Into<PathBuf>>(backend: B, dir: P, ttl: Duration) -> Cache<B> {
+        Cache {
+            backend,
+            dir: dir.into(),
+            ttl,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<B: Backend> Backend for Cache<B> {
+    async fn get(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<Response> {
+        let with_credentials = trace::redact_url(url) != url
+            || headers
+                .iter()
+                .any(|(name, value)| trace::redact_header(name, value) != *value);
+
+        if with_credentials {
+            return self.backend.get(url, headers).await;
+        }
+
+        let path = &fixture_path(&self.dir, url)?;
+
+        let age = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+
+        if matches!(age, Some(age) if age < self.ttl) {
+            if let Ok(response) = read_fixture(path, url) {
+                return Ok(response);
+            }
+        }
+
+        let response = self.backend.get(url, headers).await?;
+
+        // Errors in the cache directory are ignored, since the response
+        // is already available.
+        if response.status == 200 && trace::redact_body(&response.body).is_none() {
+            let _ = write_fixture(path, url, headers, &response);
+        }
+
+        Ok(response)
+    }
+}
+
+/// Read responses from a directory.
+///
+/// Missing files are `404` responses.
+pub struct Fixtures {
+    dir: PathBuf,
+}
+
+impl Fixtures {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Fixtures {
+        Fixtures { dir: dir.into() }
+    }
+}
+
+#[async_trait::async_trait]
+impl Backend for Fixtures {
+    async fn get(&self, url: &str, _headers: &[(&str, &str)]) -> anyhow::Result<Response> {
+        let path = fixture_path(&self.dir, url)?;
+
+        match std::fs::read(&path) {
+            Ok(body) => parse_fixture(&path, url, body),
+            Err(_) => Ok(Response {
+                url: url.to_string(),
+                status: 404,
+                headers: Vec::new(),
+                body: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Write the response to `path`, and the request and the response headers
+/// to the `.headers` file next to it. Credentials are redacted.
+fn write_fixture(
+    path: &Path,
+    url: &str,
+    headers: &[(&str, &str)],
+    response: &Response,
+) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    match trace::redact_body(&response.body) {
+        Some(body) => std::fs::write(path, body)?,
+        None => std::fs::write(path, &response.body)?,
+    }
+
+    let mut meta = String::new();
+    writeln!(meta, "> GET {}", trace::redact_url(url))?;
+    for (name, value) in headers {
+        writeln!(meta, "> {}: {}", name, trace::redact_header(name, value))?;
+    }
+
+    writeln!(meta, "< HTTP {}", response.status)?;
+    for (name, value) in &response.headers {
+        if RECORDED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+            writeln!(meta, "< {}: {}", name, value)?;
+        }
+    }
+
+    std::fs::write(path.with_extension("headers"), meta)?;
+
+    Ok(())
+}
+
+/// Read a response written by `write_fixture`.
+fn read_fixture(path: &Path, url: &str) -> anyhow::Result<Response> {
+    parse_fixture(path, url, std::fs::read(path)?)
+}
+
+/// Build the response for `body`, with the status and headers from the
+/// `.headers` file next to `path`.
+fn parse_fixture(path: &Path, url: &str, body: Vec<u8>) -> anyhow::Result<Response> {
+    let mut status = 200;
+    let mut headers = Vec::new();
+
+    if let Ok(meta) = std::fs::read_to_string(path.with_extension("headers")) {
+        // Lines for the request (starting with `>`) are ignored.
+        for line in meta.lines().filter_map(|l| l.strip_prefix("< ")) {
+            if let Some(code) = line.strip_prefix("HTTP ") {
+                status = code.trim().parse()?;
+            } else if let Some(colon) = line.find(':') {
+                let value = line[colon + 1..].trim().to_string();
+                headers.push((line[..colon].to_string(), value));
+            }
+        }
+    }
+
+    Ok(Response {
+        url: url.to_string(),
+        status,
+        headers,
+        body,
+    })
+}
+
+/// File for the response of a URL.
+///
+/// The query string is part of the file name, so every page of a paginated
+/// API is a different file.
+fn fixture_path(dir: &Path, url: &str) -> anyhow::Result<PathBuf> {
+    let url = reqwest::Url::parse(url)?;
+
+    let mut base = String::new();
+    if let Some(host) = url.host_str() {
+        base.push_str(host);
+    }
+
+    if let Some(port) = url.port() {
+        base.push_str(&format!("_{}", port));
+    }
+
+    base.push_str(url.path().trim_end_matches('/'));
+
+    if let Some(query) = url.query() {
+        base.push('@');
+        base.push_str(query);
+    }
+
+    Ok(dir.join(sanitize(&format!("{}.json", base))))
+}
+
+/// Replace characters not valid in file names on Windows.
+fn sanitize(path: &str) -> String {
+    path.chars()
+        .map(|c| match c {
+            ':' | '*' | '?' | '"' | '<' | '>' | '|' | '\\' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[test]
+fn map_urls_to_fixtures() {
+    let dir = Path::new("fixtures");
+
+    assert_eq!(
+        fixture_path(dir, "https://hub.docker.com/v2/repositories/library/redis/").unwrap(),
+        dir.join("hub.docker.com/v2/repositories/library/redis.json")
+    );
+
+    assert_eq!(
+        fixture_path(
+            dir,
+            "http://localhost:5000/v2/foo/manifests/sha256:1234?n=1"
+        )
+        .unwrap(),
+        dir.join("localhost_5000/v2/foo/manifests/sha256_1234@n=1.json")
+    );
+}
+
+#[test]
+fn record_and_replay_responses() {
+    let dir = std::env::temp_dir().join(format!("hdcquery-record-{}", std::process::id()));
+    let recorder = Recorder::new(Fixtures::new(TEST_FIXTURES), &dir);
+    let replay = Fixtures::new(&dir);
+
+    let url = "https://hub.docker.com/v2/repositories/rustlang/rust/";
+    let missing = "https://hub.docker.com/v2/repositories/foo/bar/?x=1";
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let recorded = recorder.get(url, &[]).await.unwrap();
+        let replayed = replay.get(url, &[]).await.unwrap();
+        assert_eq!(replayed.status, 200);
+        assert_eq!(replayed.body, recorded.body);
+
+        recorder.get(missing, &[]).await.unwrap();
+        let replayed = replay.get(missing, &[]).await.unwrap();
+        assert_eq!(replayed.status, 404);
+    });
+
+    // Credentials are not stored.
+    let token_dir = dir.join("source");
+    let token_url = "https://auth.docker.io/token?scope=pull";
+    let token_path = &fixture_path(&token_dir, token_url).unwrap();
+    std::fs::create_dir_all(token_path.parent().unwrap()).unwrap();
+    std::fs::write(token_path, r#"{"token":"secret","expires_in":300}"#).unwrap();
+
+    let recorder = Recorder::new(Fixtures::new(&token_dir), &dir);
+    rt.block_on(async {
+        let response = recorder
+            .get(token_url, &[("Authorization", "Basic secret")])
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&response.body).contains("secret"));
+    });
+
+    let recorded = &fixture_path(&dir, token_url).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(recorded).unwrap(),
+        r#"{"expires_in":300,"token":"[redacted]"}"#
+    );
+    assert_eq!(
+        std::fs::read_to_string(recorded.with_extension("headers")).unwrap(),
+        "> GET https://auth.docker.io/token?scope=pull\n\
+         > Authorization: Basic [redacted]\n\
+         < HTTP 200\n"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rebase_docker_hub_urls() {
+    let rebase = Rebase::new(Fixtures::new(TEST_FIXTURES), "http://localhost:5080/");
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let response = rebase
+            .get("https://hub.docker.com/v2/repositories/library/redis/", &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            response.url,
+            "http://localhost:5080/v2/repositories/library/redis/"
+        );
+
+        let response = rebase
+            .get("https://ghcr.io/v2/foo/tags/list", &[])
+            .await
+            .unwrap();
+        assert_eq!(response.url, "https://ghcr.io/v2/foo/tags/list");
+    });
+}
+
+#[test]
+fn cache_responses() {
+    let dir = std::env::temp_dir().join(format!("hdcquery-cache-{}", std::process::id()));
+    let cache_dir = dir.join("cache");
+    let empty = dir.join("empty");
+    let ttl = Duration::from_secs(60);
+
+    let url = "https://hub.docker.com/v2/repositories/rustlang/rust/";
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        // Requests with credentials are not cached.
+        let cache = Cache::new(Fixtures::new(TEST_FIXTURES), &cache_dir, ttl);
+        let response = cache.get(url, &[("Authorization", "Bearer abc")]).await;
+        assert_eq!(response.unwrap().status, 200);
+        assert!(!cache_dir.exists());
+
+        let response = cache.get(url, &[]).await.unwrap();
+        assert_eq!(response.status, 200);
+
+        // The second response is read from the cache.
+        let cache = Cache::new(Fixtures::new(&empty), &cache_dir, ttl);
+        assert_eq!(cache.get(url, &[]).await.unwrap().body, response.body);
+
+        // Expired responses are not used.
+        let cache = Cache::new(Fixtures::new(&empty), &cache_dir, Duration::from_secs(0));
+        assert_eq!(cache.get(url, &[]).await.unwrap().status, 404);
+    });
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,694 @@
+//! Configuration file, in `$XDG_CONFIG_HOME/hdcquery/config.toml`.
+//!
+//! The file provides default values for some options. Options given in the
+//! command line take precedence over it.
+//!
+//! Only a subset of TOML is supported: tables, and keys with strings or
+//! integers.
+//!
+//! ```toml
+//! pager = "less -R"
+//!
+//! [tags]
+//! limit = 50
+//! view = "group"
+//! ```
+
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::credentials;
+use crate::options::{self, ConfigCommand, ConfigOptions, OutputFormat, PlatformArg, SearchSort};
+use crate::tags::View;
+
+const FILE_NAME: &str = "config.toml";
+
+/// Prefix for the keys in the `[alias]` table.
+const ALIAS_PREFIX: &str = "alias.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    String,
+    Integer,
+}
+
+/// Keys accepted in the configuration file.
+const KEYS: &[(&str, Kind, &str)] = &[
+    (
+        "pager",
+        Kind::String,
+        "Command to execute the pager, if $HDC_PAGER is not set",
+    ),
+    (
+        "hub-url",
+        Kind::String,
+        "Server for requests to hub.docker.com (like --hub-url)",
+    ),
+    (
+        "proxy",
+        Kind::String,
+        "Proxy for all requests (like --proxy)",
+    ),
+    (
+        "timeout",
+        Kind::Integer,
+        "Maximum time for a request, in seconds",
+    ),
+    (
+        "connect-timeout",
+        Kind::Integer,
+        "Maximum time to connect to a server, in seconds",
+    ),
+    (
+        "cache.ttl",
+        Kind::Integer,
+        "Reuse cached responses newer than this, in seconds",
+    ),
+    ("cache.dir", Kind::String, "Directory for cached responses"),
+    (
+        "credentials",
+        Kind::String,
+        "Credentials for other registries: none or docker",
+    ),
+    (
+        "output",
+        Kind::String,
+        "Output format: text or json (like --output)",
+    ),
+    (
+        "search.columns",
+        Kind::String,
+        "Columns in the search command",
+    ),
+    (
+        "search.sort",
+        Kind::String,
+        "Sort results by pulls, stars or updated",
+    ),
+    ("tags.columns", Kind::String, "Columns in the tags command"),
+    (
+        "tags.limit",
+        Kind::Integer,
+        "Number of results for every repository",
+    ),
+    (
+        "tags.platform",
+        Kind::String,
+        "Filter by platform (like linux/arm/v7)",
+    ),
+    (
+        "tags.view",
+        Kind::String,
+        "Default view: images, group, tree or aliases",
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+}
+
+/// Values are written in TOML syntax.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(s) => {
+                f.write_str("\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        c if c.is_control() => write!(f, "\\u{:04X}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+
+            Value::Integer(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Values in the file, with the full key (like `tags.limit`).
+    values: Vec<(String, Value)>,
+}
+
+impl Config {
+    /// Load the configuration file. If it does not exist, the configuration
+    /// is empty.
+    pub fn load() -> anyhow::Result<Config> {
+        let path = match path() {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(source) => Config::parse(&source).with_context(|| format!("{}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e).with_context(|| format!("can't read {}", path.display())),
+        }
+    }
+
+    fn parse(source: &str) -> anyhow::Result<Config> {
+        let mut values = Vec::new();
+
+        for (number, line) in lines(source) {
+            let (key, value) = match line {
+                Line::Pair(key, value) => (key, value),
+                Line::Invalid => anyhow::bail!("line {}: invalid syntax", number),
+                _ => continue,
+            };
+
+            let value = parse_value(value)
+                .with_context(|| format!("line {}: invalid value for {}", number, key))?;
+
+            check_key(&key, &value).with_context(|| format!("line {}", number))?;
+            values.push((key, value));
+        }
+
+        Ok(Config { values })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        // If a key is repeated, the last one is used.
+        self.values
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Aliases defined in the `[alias]` table, as `(name, command line)`.
+    pub fn aliases(&self) -> Vec<(&str, &str)> {
+        let mut aliases: Vec<(&str, &str)> = Vec::new();
+
+        for (key, value) in &self.values {
+            if let (Some(name), Value::String(command)) = (key.strip_prefix(ALIAS_PREFIX), value) {
+                aliases.retain(|(n, _)| *n != name);
+                aliases.push((name, command));
+            }
+        }
+
+        aliases
+    }
+
+    fn string(&self, key: &str) -> Option<String> {
+        match self.get(key) {
+            Some(Value::String(s)) => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    fn integer<T: std::convert::TryFrom<i64>>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        match self.get(key) {
+            Some(Value::Integer(n)) => match T::try_from(*n) {
+                Ok(n) => Ok(Some(n)),
+                Err(_) => anyhow::bail!("{}: {} is out of range", key, n),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Fill the options not given in the command line.
+    pub fn apply(&self, options: &mut options::Options) -> anyhow::Result<()> {
+        if let Some(pager) = self.string("pager") {
+            crate::pager::set_command(pager);
+        }
+
+        if options.hub_url.is_none() {
+            options.hub_url = self.string("hub-url");
+        }
+
+        if options.proxy.is_none() {
+            options.proxy = self.string("proxy");
+        }
+
+        if options.timeout.is_none() {
+            options.timeout = self.integer("timeout")?;
+        }
+
+        if options.connect_timeout.is_none() {
+            options.connect_timeout = self.integer("connect-timeout")?;
+        }
+
+        if options.cache_ttl.is_none() {
+            options.cache_ttl = self.integer("cache.ttl")?;
+        }
+
+        if options.cache_dir.is_none() {
+            options.cache_dir = self.string("cache.dir").map(PathBuf::from);
+        }
+
+        if options.credentials.is_none() {
+            options.credentials = self.parsed("credentials")?;
+        }
+
+        if options.output.is_none() {
+            options.output = self.parsed("output")?;
+        }
+
+        match &mut options.command {
+            Some(options::Command::Search(search)) => {
+                if search.columns.is_none() {
+                    search.columns = self.string("search.columns");
+                }
+
+                if search.sort.is_none() {
+                    search.sort = self.parsed("search.sort")?;
+                }
+            }
+
+            Some(options::Command::Tags(tags)) => {
+                if tags.columns.is_none() {
+                    tags.columns = self.string("tags.columns");
+                }
+
+                if tags.limit.is_none() {
+                    tags.limit = self.integer("tags.limit")?;
+                }
+
+                let has_platform = tags.platform.is_some()
+                    || tags.current_machine
+                    || tags.architecture.is_some()
+                    || tags.operating_system.is_some();
+
+                if !has_platform {
+                    tags.platform = self.parsed("tags.platform")?;
+                }
+
+                if tags.view.is_none() && !(tags.group || tags.tree || tags.aliases) {
+                    tags.view = self.parsed("tags.view")?;
+                }
+            }
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Value of a string key, parsed with `FromStr`.
+    fn parsed<T>(&self, key: &str) -> anyhow::Result<Option<T>>
+    where
+        T: std::str::FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.string(key) {
+            Some(s) => match s.parse() {
+                Ok(v) => Ok(Some(v)),
+                Err(e) => anyhow::bail!("{}: {}", key, e),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Path of the configuration file.
+pub fn path() -> Option<PathBuf> {
+    crate::xdg::config_home().map(|dir| dir.join(FILE_NAME))
+}
+
+/// Execute the `config` command.
+pub fn run(options: ConfigOptions) -> anyhow::Result<()> {
+    match options.command {
+        Some(ConfigCommand::Get(opts)) => {
+            find_key(&opts.key)?;
+            match Config::load()?.get(&opts.key) {
+                Some(Value::String(s)) => println!("{}", s),
+                Some(value) => println!("{}", value),
+                None => anyhow::bail!("{} is not set", opts.key),
+            }
+        }
+
+        Some(ConfigCommand::Set(opts)) => {
+            let value = match find_key(&opts.key)? {
+                Kind::String => Value::String(opts.value),
+                Kind::Integer => match opts.value.parse() {
+                    Ok(n) => Value::Integer(n),
+                    Err(_) => anyhow::bail!("{} must be an integer", opts.key),
+                },
+            };
+
+            check_key(&opts.key, &value)?;
+
+            let path = path().context("can't find the configuration directory")?;
+            let source = match std::fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e).with_context(|| format!("can't read {}", path.display())),
+            };
+
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::write(&path, set_value(&source, &opts.key, &value))?;
+        }
+
+        Some(ConfigCommand::List(_)) => {
+            let config = Config::load()?;
+            for (key, _, _) in KEYS {
+                if let Some(value) = config.get(key) {
+                    println!("{} = {}", key, value);
+                }
+            }
+
+            for (name, command) in config.aliases() {
+                println!(
+                    "{}{} = {}",
+                    ALIAS_PREFIX,
+                    name,
+                    Value::String(command.into())
+                );
+            }
+        }
+
+        Some(ConfigCommand::Keys(_)) => {
+            for (key, _, help) in KEYS {
+                println!("{:16} {}", key, help);
+            }
+
+            println!("{:16} Command line for the alias NAME", "alias.NAME");
+        }
+
+        None => eprintln!("Missing config command. Use --help for more info."),
+    }
+
+    Ok(())
+}
+
+fn find_key(key: &str) -> anyhow::Result<Kind> {
+    if key.starts_with(ALIAS_PREFIX) {
+        return Ok(Kind::String);
+    }
+
+    match KEYS.iter().find(|(k, _, _)| *k == key) {
+        Some((_, kind, _)) => Ok(*kind),
+        None => anyhow::bail!(
+            "unknown key {:?}. Use `config keys` to list valid keys",
+            key
+        ),
+    }
+}
+
+/// Check that the key is valid, and that its value has the expected type.
+fn check_key(key: &str, value: &Value) -> anyhow::Result<()> {
+    if let Some(name) = key.strip_prefix(ALIAS_PREFIX) {
+        crate::alias::check_name(name)?;
+    }
+
+    let valid = match (find_key(key)?, value) {
+        (Kind::String, Value::String(s)) => match key {
+            "credentials" => s.parse::<credentials::Source>().is_ok(),
+            "output" => s.parse::<OutputFormat>().is_ok(),
+            "search.sort" => s.parse::<SearchSort>().is_ok(),
+            "tags.platform" => s.parse::<PlatformArg>().is_ok(),
+            "tags.view" => s.parse::<View>().is_ok(),
+            _ => true,
+        },
+
+        (Kind::Integer, Value::Integer(n)) => match key {
+            "timeout" | "connect-timeout" => *n >= 1,
+            _ => *n >= 0,
+        },
+
+        _ => false,
+    };
+
+    if !valid {
+        anyhow::bail!("invalid value for {}: {}", key, value);
+    }
+
+    Ok(())
+}
+
+enum Line<'a> {
+    Empty,
+    Table(&'a str),
+    Pair(String, &'a str),
+    Invalid,
+}
+
+/// Parse the lines of the file. Keys in pairs include the table name.
+fn lines(source: &str) -> impl Iterator<Item = (usize, Line<'_>)> {
+    let mut table = "";
+
+    source.lines().enumerate().map(move |(index, line)| {
+        let line = line.trim();
+
+        let parsed = if line.is_empty() || line.starts_with('#') {
+            Line::Empty
+        } else if line.starts_with('[') {
+            match line.find(']') {
+                Some(end) if !line.starts_with("[[") && is_comment(&line[end + 1..]) => {
+                    table = line[1..end].trim();
+                    Line::Table(table)
+                }
+                _ => Line::Invalid,
+            }
+        } else {
+            match line.find('=') {
+                Some(eq) if eq > 0 => {
+                    let key = unquote_key(line[..eq].trim());
+                    let key = if table.is_empty() {
+                        key
+                    } else {
+                        format!("{}.{}", table, key)
+                    };
+
+                    Line::Pair(key, line[eq + 1..].trim())
+                }
+                _ => Line::Invalid,
+            }
+        };
+
+        (index + 1, parsed)
+    })
+}
+
+fn unquote_key(key: &str) -> String {
+    key.split('.')
+        .map(|k| k.trim().trim_matches('"'))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Check if `rest` is empty, or only has a comment.
+fn is_comment(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+/// Parse a value, ignoring trailing comments.
+fn parse_value(source: &str) -> anyhow::Result<Value> {
+    let (value, rest) = split_value(source)?;
+
+    if !is_comment(rest) {
+        anyhow::bail!("unexpected {:?}", rest.trim());
+    }
+
+    Ok(value)
+}
+
+/// Parse the value at the beginning of `source`, and return the text after
+/// it.
+fn split_value(source: &str) -> anyhow::Result<(Value, &str)> {
+    let (value, rest) = if let Some(s) = source.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = s.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((i, '"')) => break i,
+                Some((_, '\\')) => match chars.next().map(|(_, c)| c) {
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(std::char::from_u32)
+                            .context("invalid escape")?;
+                        value.push(c);
+                    }
+                    _ => anyhow::bail!("invalid escape"),
+                },
+                Some((_, c)) => value.push(c),
+                None => anyhow::bail!("unterminated string"),
+            }
+        };
+
+        (Value::String(value), &s[end + 1..])
+    } else if let Some(s) = source.strip_prefix('\'') {
+        let end = s.find('\'').context("unterminated string")?;
+        (Value::String(s[..end].to_string()), &s[end + 1..])
+    } else {
+        let end = source.find('#').unwrap_or(source.len());
+        let token = source[..end].trim();
+        (
+            Value::Integer(token.replace('_', "").parse()?),
+            &source[end..],
+        )
+    };
+
+    Ok((value, rest))
+}
+
+/// Update the value for `key` in the source of the file, keeping the other
+/// lines and comments.
+fn set_value(source: &str, key: &str, value: &Value) -> String {
+    let (table, name) = match key.rfind('.') {
+        Some(dot) => (&key[..dot], &key[dot + 1..]),
+        None => ("", key),
+    };
+
+    let mut output: Vec<String> = source.lines().map(str::to_string).collect();
+    let new_line = format!("{} = {}", name, value);
+
+    // Replace an existing key, or add it after the last line of its table.
+    let mut insert_at = None;
+    let mut current_table = "";
+    let mut replaced = false;
+    for (index, line) in lines(source) {
+        match &line {
+            Line::Table(t) => current_table = t,
+            Line::Pair(k, old) if k == key => {
+                let mut line = if current_table == table {
+                    new_line.clone()
+                } else {
+                    format!("{} = {}", key, value)
+                };
+
+                if let Ok((_, rest)) = split_value(old) {
+                    if rest.trim().starts_with('#') {
+                        line.push(' ');
+                        line.push_str(rest.trim());
+                    }
+                }
+
+                output[index - 1] = line;
+                replaced = true;
+            }
+            _ => (),
+        }
+
+        if current_table == table && !matches!(line, Line::Empty) {
+            insert_at = Some(index);
+        }
+    }
+
+    if !replaced {
+        match insert_at {
+            Some(index) => output.insert(index, new_line),
+            None if table.is_empty() => output.insert(0, new_line),
+            None => {
+                if output.last().map(|l| !l.trim().is_empty()).unwrap_or(false) {
+                    output.push(String::new());
+                }
+
+                output.push(format!("[{}]", table));
+                output.push(new_line);
+            }
+        }
+    }
+
+    let mut output = output.join("\n");
+    output.push('\n');
+    output
+}
+
+#[test]
+fn parse_and_update_config() {
+    let source = concat!(
+        "# Defaults\n",
+        "pager = \"less -R\" # comment\n",
+        "\n",
+        "[tags]\n",
+        "limit = 1_000\n",
+        "view = 'tree'\n",
+    );
+
+    let config = Config::parse(source).unwrap();
+    assert_eq!(config.get("pager"), Some(&Value::String("less -R".into())));
+    assert_eq!(config.get("tags.limit"), Some(&Value::Integer(1000)));
+    assert_eq!(config.get("tags.view"), Some(&Value::String("tree".into())));
+
+    assert!(Config::parse("tags.limit = \"x\"").is_err());
+    assert!(Config::parse("foo = 1").is_err());
+    assert!(Config::parse("[tags]\nview = \"table\"").is_err());
+    assert!(Config::parse("[tags] limit = 1").is_err());
+    assert!(Config::parse("[tags] # comment\nlimit = 1").is_ok());
+    assert!(Config::parse("timeout = 0").is_err());
+    assert!(Config::parse("tags.limit = true").is_err());
+
+    let source = set_value(source, "pager", &Value::String("more".into()));
+    let source = set_value(&source, "tags.limit", &Value::Integer(5));
+    let source = set_value(&source, "hub-url", &Value::String("http://a\"b".into()));
+    let source = set_value(&source, "search.sort", &Value::String("stars".into()));
+    assert_eq!(
+        source,
+        concat!(
+            "# Defaults\n",
+            "pager = \"more\" # comment\n",
+            "hub-url = \"http://a\\\"b\"\n",
+            "\n",
+            "[tags]\n",
+            "limit = 5\n",
+            "view = 'tree'\n",
+            "\n",
+            "[search]\n",
+            "sort = \"stars\"\n",
+        )
+    );
+
+    let config = Config::parse(&source).unwrap();
+    assert_eq!(
+        config.get("hub-url"),
+        Some(&Value::String("http://a\"b".into()))
+    );
+    assert_eq!(
+        set_value("", "tags.view", &Value::String("group".into())),
+        "[tags]\nview = \"group\"\n"
+    );
+}
+
+#[test]
+fn override_config_with_options() {
+    use gumdrop::Options;
+
+    let config = Config::parse("[tags]\nplatform = \"linux/arm64\"\nview = \"tree\"\n").unwrap();
+
+    let tags = |args: &[&str]| {
+        let mut options = options::Options::parse_args_default(args).unwrap();
+        config.apply(&mut options).unwrap();
+        match options.command {
+            Some(options::Command::Tags(tags)) => (tags.view, tags.platform),
+            _ => unreachable!(),
+        }
+    };
+
+    assert_eq!(
+        tags(&["tags", "redis"]),
+        (
+            Some(View::Tree),
+            Some(PlatformArg::Only("linux/arm64".parse().unwrap()))
+        )
+    );
+
+    assert_eq!(
+        tags(&["tags", "--view", "images", "--platform", "all", "redis"]),
+        (Some(View::Images), Some(PlatformArg::All))
+    );
+
+    assert_eq!(tags(&["tags", "-g", "-a", "amd64", "redis"]), (None, None));
+}
@@ -0,0 +1,137 @@
+//! Credentials for registries, with `--credentials`.
+//!
+//! With `docker`, credentials are read from the `auths` table in the
+//! configuration file of Docker (`$DOCKER_CONFIG/config.json`, or
+//! `~/.docker/config.json`), written by `docker login`. Credential helpers
+//! (`credsStore` and `credHelpers`) are not supported.
+//!
+//! Credentials are sent only to the authentication server of a registry,
+//! to request a token. Docker Hub is always queried anonymously.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+
+/// Source of the credentials.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    /// Request anonymous tokens.
+    None,
+
+    /// Read credentials from the configuration file of Docker.
+    Docker,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Source::None),
+            "docker" => Ok(Source::Docker),
+            _ => Err(format!("invalid credentials source {:?}", s)),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Default)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuth>,
+}
+
+#[derive(serde::Deserialize)]
+struct DockerAuth {
+    /// `user:password`, encoded in base64.
+    auth: Option<String>,
+}
+
+static SOURCE: OnceCell<Source> = OnceCell::new();
+
+/// Entries of `auths`, loaded when they are needed for the first time.
+static DOCKER_AUTHS: OnceCell<DockerConfig> = OnceCell::new();
+
+/// Set the source of the credentials. By default, no credentials are used.
+pub fn set_source(source: Source) {
+    let _ = SOURCE.set(source);
+}
+
+/// Value for the `Authorization` header in token requests for `registry`.
+pub fn authorization(registry: &str) -> anyhow::Result<Option<String>> {
+    match SOURCE.get() {
+        Some(Source::Docker) => {
+            let config = DOCKER_AUTHS.get_or_try_init(load_docker_config)?;
+            Ok(find_auth(config, registry).map(|auth| format!("Basic {}", auth)))
+        }
+
+        Some(Source::None) | None => Ok(None),
+    }
+}
+
+/// Path of the configuration file of Docker.
+fn docker_config_path() -> Option<PathBuf> {
+    match std::env::var_os("DOCKER_CONFIG") {
+        Some(dir) => Some(PathBuf::from(dir).join("config.json")),
+        None => Some(PathBuf::from(std::env::var_os("HOME")?).join(".docker/config.json")),
+    }
+}
+
+fn load_docker_config() -> anyhow::Result<DockerConfig> {
+    let path = match docker_config_path() {
+        Some(path) => path,
+        None => return Ok(DockerConfig::default()),
+    };
+
+    match std::fs::read(&path) {
+        Ok(source) => {
+            serde_json::from_slice(&source).with_context(|| format!("{}", path.display()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DockerConfig::default()),
+        Err(e) => Err(e).with_context(|| format!("can't read {}", path.display())),
+    }
+}
+
+/// Find the credentials for `registry`.
+///
+/// Keys in `auths` can be a host (`ghcr.io`) or a URL
+/// (`https://ghcr.io/v1/`).
+fn find_auth<'a>(config: &'a DockerConfig, registry: &str) -> Option<&'a str> {
+    config.auths.iter().find_map(|(key, entry)| {
+        let host = key
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .split('/')
+            .next()?;
+
+        if host.eq_ignore_ascii_case(registry) {
+            entry.auth.as_deref().filter(|auth| !auth.is_empty())
+        } else {
+            None
+        }
+    })
+}
+
+#[test]
+fn find_docker_credentials() {
+    let config: DockerConfig = serde_json::from_str(
+        r#"{
+            "auths": {
+                "https://index.docker.io/v1/": { "auth": "aHViOnB3" },
+                "ghcr.io": { "auth": "Z2g6cHc=" },
+                "localhost:5000": { "auth": "bG9jYWw6cHc=" },
+                "quay.io": {}
+            },
+            "credsStore": "desktop"
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(find_auth(&config, "ghcr.io"), Some("Z2g6cHc="));
+    assert_eq!(find_auth(&config, "localhost:5000"), Some("bG9jYWw6cHc="));
+    assert_eq!(find_auth(&config, "index.docker.io"), Some("aHViOnB3"));
+    assert_eq!(find_auth(&config, "quay.io"), None);
+    assert_eq!(find_auth(&config, "localhost"), None);
+}
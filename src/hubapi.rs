@@ -1,27 +1,144 @@
 //! Types and common functions to use API from hub.docker.com
 
+use anyhow::Context;
 use chrono::prelude::*;
 use std::time::Duration;
 
-pub fn http_client() -> Result<reqwest::Client, reqwest::Error> {
-    reqwest::Client::builder()
+use crate::options::Options;
+
+const DEFAULT_TIMEOUT: u64 = 30;
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// Build the client for all HTTP requests.
+///
+/// Without `--proxy`, the proxies are read from the `HTTP_PROXY`,
+/// `HTTPS_PROXY` and `NO_PROXY` variables.
+pub fn http_client(options: &Options) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
         .user_agent(concat!("dhrb/", env!("CARGO_PKG_VERSION")))
-        .timeout(Duration::from_secs(30))
-        .connect_timeout(Duration::from_secs(10))
-        .build()
+        .timeout(Duration::from_secs(
+            options.timeout.unwrap_or(DEFAULT_TIMEOUT),
+        ))
+        .connect_timeout(Duration::from_secs(
+            options.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+        ));
+
+    if let Some(proxy) = &options.proxy {
+        let proxy = reqwest::Url::parse(proxy).context("invalid --proxy")?;
+        let no_proxy = std::env::var("NO_PROXY")
+            .or_else(|_| std::env::var("no_proxy"))
+            .unwrap_or_default();
+
+        builder = builder.proxy(reqwest::Proxy::custom(move |url| {
+            if no_proxy_matches(&no_proxy, url.host_str().unwrap_or_default()) {
+                None
+            } else {
+                Some(proxy.clone())
+            }
+        }));
+    }
+
+    if let Some(path) = &options.cacert {
+        let bundle = std::fs::read_to_string(path)
+            .with_context(|| format!("can't read {}", path.display()))?;
+
+        // A bundle can contain multiple certificates, but `from_pem` only
+        // reads the first one.
+        for pem in bundle.split_terminator(PEM_END) {
+            if pem.trim().is_empty() {
+                continue;
+            }
+
+            let pem = format!("{}{}\n", pem, PEM_END);
+            let cert = reqwest::Certificate::from_pem(pem.as_bytes())
+                .with_context(|| format!("invalid certificate in {}", path.display()))?;
+
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    if options.insecure {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Check if `host` is in a `NO_PROXY` list.
+///
+/// Entries are separated by commas. An entry matches the host itself and
+/// its subdomains, and `*` matches every host.
+fn no_proxy_matches(no_proxy: &str, host: &str) -> bool {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase();
+
+    no_proxy.split(',').any(|entry| {
+        let entry = entry.trim().trim_start_matches('.').to_ascii_lowercase();
+        entry == "*"
+            || (!entry.is_empty() && (host == entry || host.ends_with(&format!(".{}", entry))))
+    })
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Summary {
-    pub name: String,
     pub slug: String,
     pub updated_at: DateTime<Utc>,
+
+    /// Written as a number in the JSON output.
+    #[serde(serialize_with = "serialize_pull_count")]
     pub pull_count: Option<String>,
     pub star_count: Option<usize>,
     pub short_description: Option<String>,
+    pub filter_type: Option<String>,
+}
+
+impl Summary {
+    /// Number of pulls, parsed from `pull_count`.
+    ///
+    /// The API returns approximate values, like `"10M+"` or `"500K+"`. In
+    /// such cases, the lower bound is returned.
+    pub fn pulls(&self) -> Option<u64> {
+        self.pull_count.as_deref().and_then(parse_pull_count)
+    }
+
+    /// Label for images from trusted sources.
+    pub fn trust_label(&self) -> &'static str {
+        match self.filter_type.as_deref() {
+            Some("official") => "official",
+            Some("store") | Some("verified_publisher") => "verified",
+            _ => "",
+        }
+    }
 }
 
-#[derive(serde::Deserialize, Debug)]
+/// Parse a pull count like `"10M+"` to a number.
+pub fn parse_pull_count(value: &str) -> Option<u64> {
+    let value = value.trim().trim_end_matches('+');
+
+    let (number, multiplier) = match value.char_indices().last()? {
+        (i, 'K') | (i, 'k') => (&value[..i], 1e3),
+        (i, 'M') | (i, 'm') => (&value[..i], 1e6),
+        (i, 'B') | (i, 'b') => (&value[..i], 1e9),
+        _ => (value, 1.0),
+    };
+
+    let number: f64 = number.parse().ok()?;
+    Some((number * multiplier) as u64)
+}
+
+fn serialize_pull_count<S>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_some(&value.as_deref().and_then(parse_pull_count))
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Repository {
     pub namespace: Option<String>,
     pub name: Option<String>,
@@ -33,17 +150,87 @@ pub struct Repository {
     pub last_updated: Option<DateTime<Utc>>,
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Tag {
     pub name: String,
     pub images: Vec<Image>,
     pub last_updated: Option<DateTime<Utc>>,
+
+    /// Set if the tag has attestation manifests, even if they are removed
+    /// from `images`.
+    #[serde(skip_deserializing)]
+    pub attestations: bool,
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Image {
     pub architecture: String,
+    pub variant: Option<String>,
     pub os: String,
+    pub os_version: Option<String>,
     pub size: u64,
     pub digest: Option<String>,
 }
+
+impl Image {
+    /// Architecture of the image, followed by its variant, like `arm/v7`.
+    pub fn architecture_variant(&self) -> String {
+        match &self.variant {
+            Some(variant) if !variant.is_empty() => format!("{}/{}", self.architecture, variant),
+            _ => self.architecture.clone(),
+        }
+    }
+
+    /// Platform of the image, like `linux/amd64` or `linux/arm/v7`.
+    pub fn platform(&self) -> String {
+        if self.is_attestation() {
+            return "attestation".to_string();
+        }
+
+        format!("{}/{}", self.os, self.architecture_variant())
+    }
+
+    /// Check if this is an attestation manifest (SBOM or provenance), added
+    /// by BuildKit.
+    ///
+    /// Docker Hub reports them as images with `unknown` platform.
+    pub fn is_attestation(&self) -> bool {
+        self.os == "unknown" && self.architecture == "unknown"
+    }
+}
+
+#[test]
+fn parse_pull_counts() {
+    assert_eq!(parse_pull_count("8854"), Some(8854));
+    assert_eq!(parse_pull_count("500K+"), Some(500_000));
+    assert_eq!(parse_pull_count("10M+"), Some(10_000_000));
+    assert_eq!(parse_pull_count("1.5B+"), Some(1_500_000_000));
+    assert_eq!(parse_pull_count("+"), None);
+    assert_eq!(parse_pull_count("many"), None);
+}
+
+#[test]
+fn serialize_pull_count_as_number() {
+    let summary: Summary = serde_json::from_str(
+        r#"{"slug": "redis", "updated_at": "2024-01-01T00:00:00Z", "pull_count": "1B+"}"#,
+    )
+    .unwrap();
+
+    let value = serde_json::to_value(&summary).unwrap();
+    assert_eq!(value["pull_count"], 1_000_000_000);
+}
+
+#[test]
+fn match_no_proxy_hosts() {
+    let no_proxy = "localhost, .internal.example.com,10.0.0.1,::1";
+
+    assert!(no_proxy_matches(no_proxy, "localhost"));
+    assert!(no_proxy_matches(no_proxy, "registry.internal.example.com"));
+    assert!(no_proxy_matches(no_proxy, "internal.example.com"));
+    assert!(no_proxy_matches(no_proxy, "10.0.0.1"));
+    assert!(no_proxy_matches(no_proxy, "[::1]"));
+    assert!(!no_proxy_matches(no_proxy, "hub.docker.com"));
+    assert!(!no_proxy_matches(no_proxy, "notinternal.example.com"));
+    assert!(!no_proxy_matches("", "hub.docker.com"));
+    assert!(no_proxy_matches("*", "hub.docker.com"));
+}
@@ -1,9 +1,18 @@
 use chrono::prelude::*;
+use unicode_width::UnicodeWidthChar;
 
 pub trait DurationExt {
     fn to_human(&self) -> String;
 }
 
+pub trait StrExt {
+    /// Width of the string in a terminal, ignoring ANSI escape sequences.
+    fn display_width(&self) -> usize;
+
+    /// Remove ANSI escape sequences from the string.
+    fn strip_ansi(&self) -> String;
+}
+
 impl DurationExt for DateTime<chrono::Utc> {
     fn to_human(&self) -> String {
         let time_ago = Utc::now() - *self;
@@ -32,6 +41,30 @@ impl DurationExt for DateTime<chrono::Utc> {
     }
 }
 
+impl StrExt for str {
+    fn display_width(&self) -> usize {
+        self.strip_ansi().chars().filter_map(|c| c.width()).sum()
+    }
+
+    fn strip_ansi(&self) -> String {
+        let mut output = String::with_capacity(self.len());
+        let mut chars = self.chars();
+
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                // Skip until the final byte of the sequence.
+                if chars.next() == Some('[') {
+                    chars.by_ref().find(|c| ('\x40'..='\x7e').contains(c));
+                }
+            } else {
+                output.push(c);
+            }
+        }
+
+        output
+    }
+}
+
 #[test]
 fn compute_durations() {
     macro_rules! check {
@@ -53,3 +86,11 @@ fn compute_durations() {
         "2000-01-02".to_string()
     );
 }
+
+#[test]
+fn measure_strings() {
+    assert_eq!("abc".display_width(), 3);
+    assert_eq!("\x1b[1mbold\x1b[22m".display_width(), 4);
+    assert_eq!("\x1b[1mbold\x1b[22m".strip_ansi(), "bold");
+    assert_eq!("日本".display_width(), 4);
+}
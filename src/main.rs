@@ -1,25 +1,118 @@
-use gumdrop::Options;
+use std::sync::Arc;
 
+use anyhow::Context;
+
+mod alias;
+mod backend;
+mod config;
+mod credentials;
 mod hubapi;
 mod langext;
+mod markdown;
+mod mockhub;
 mod options;
 mod pager;
+mod pages;
+mod platform;
+mod plugin;
+mod prompt;
+mod reference;
+mod registry;
 mod search;
 mod show;
+mod table;
 mod tags;
+mod trace;
+mod xdg;
 
 use options::Command as C;
 
 fn main() -> anyhow::Result<()> {
     let mut rt = tokio::runtime::Runtime::new()?;
-    let options = options::Options::parse_args_default_or_exit();
 
-    match options.command {
-        Some(C::Search(opts)) => rt.block_on(search::run(opts))?,
-        Some(C::Show(opts)) => rt.block_on(show::run(opts))?,
-        Some(C::Tags(opts)) => rt.block_on(tags::run(opts))?,
+    let config = config::Config::load();
+    let aliases = config.as_ref().map(|c| c.aliases()).unwrap_or_default();
+    let args = alias::expand(std::env::args().skip(1).collect(), &aliases)?;
+
+    // Commands not found in the built-in commands are executed as plugins.
+    if let Some(plugin) = plugin::Plugin::find(&args) {
+        let mut options = options::parse_args_or_exit(&args[..plugin.index], &aliases);
+        config?.apply(&mut options)?;
+        std::process::exit(plugin.run(&args, &options)?);
+    }
+
+    let mut options = options::parse_args_or_exit(&args, &aliases);
+
+    // Errors in the configuration file are ignored for the `config`
+    // command, so it can be used to fix an invalid file.
+    if !matches!(options.command, Some(C::Config(_))) {
+        config?.apply(&mut options)?;
+    }
+
+    if options.no_pager {
+        pager::disable();
+    }
+
+    if let Some(source) = options.credentials {
+        credentials::set_source(source);
+    }
+
+    let format = options.output.unwrap_or_default();
+
+    match options.command.take() {
+        Some(C::Search(opts)) => rt.block_on(search::run(opts, &backend(&options)?, format))?,
+        Some(C::Show(opts)) => rt.block_on(show::run(opts, &backend(&options)?, format))?,
+        Some(C::Tags(opts)) => rt.block_on(tags::run(opts, &backend(&options)?, format))?,
+        Some(C::MockServer(opts)) => rt.block_on(mockhub::run(opts))?,
+        Some(C::Config(opts)) => config::run(opts)?,
         None => eprintln!("Missing command. Use --help for more info."),
     }
 
     Ok(())
 }
+
+/// Backend to send the requests of the commands, from the global options.
+fn backend(options: &options::Options) -> anyhow::Result<Arc<dyn backend::Backend>> {
+    // Sessions are recorded without the cache, so all requests are sent.
+    let cache_ttl = match options.record {
+        Some(_) => 0,
+        None => options.cache_ttl.unwrap_or(0),
+    };
+
+    let network = || -> anyhow::Result<Arc<dyn backend::Backend>> {
+        let mut http: Arc<dyn backend::Backend> =
+            Arc::new(backend::Http::new(hubapi::http_client(options)?));
+
+        // The cache is inside `Rebase`, so responses from the server in
+        // `--hub-url` are stored with its own URLs.
+        if cache_ttl > 0 {
+            let dir = match &options.cache_dir {
+                Some(dir) => dir.clone(),
+                None => xdg::cache_home()
+                    .context("can't find the cache directory")?
+                    .join("http"),
+            };
+
+            let ttl = std::time::Duration::from_secs(cache_ttl);
+            http = Arc::new(backend::Cache::new(http, dir, ttl));
+        }
+
+        Ok(match &options.hub_url {
+            Some(url) => Arc::new(backend::Rebase::new(http, url)),
+            None => Arc::new(http),
+        })
+    };
+
+    let mut backend: Arc<dyn backend::Backend> = match (&options.replay, &options.record) {
+        (Some(_), Some(_)) => anyhow::bail!("--record and --replay can't be used together"),
+        (Some(dir), None) => Arc::new(backend::Fixtures::new(dir)),
+        (None, Some(dir)) => Arc::new(backend::Recorder::new(network()?, dir)),
+        (None, None) => network()?,
+    };
+
+    if options.trace_http > 0 {
+        backend = Arc::new(trace::Trace::new(backend, options.trace_http));
+    }
+
+    Ok(backend)
+}
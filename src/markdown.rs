@@ -0,0 +1,605 @@
+//! Render Markdown documents for a terminal.
+//!
+//! Paragraphs are wrapped to the width of the terminal. Links are replaced
+//! by numbered references, which are printed at the end of the document.
+//!
+//! If `ansi` is enabled, headings, emphasis and code are styled with ANSI
+//! escape sequences. Otherwise, the output is plain text.
+//!
+//! Raw HTML is discarded, except for `<br>` tags.
+
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, Options, Parser, Tag};
+
+use crate::langext::StrExt;
+
+const BOLD: (&str, &str) = ("\x1b[1m", "\x1b[22m");
+const ITALIC: (&str, &str) = ("\x1b[3m", "\x1b[23m");
+const UNDERLINE: (&str, &str) = ("\x1b[4m", "\x1b[24m");
+const STRIKE: (&str, &str) = ("\x1b[9m", "\x1b[29m");
+const CODE: (&str, &str) = ("\x1b[36m", "\x1b[39m");
+
+/// Render the Markdown document in `source`.
+pub fn render(source: &str, width: usize, ansi: bool) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+
+    let mut renderer = Renderer {
+        width,
+        ansi,
+        ..Renderer::default()
+    };
+
+    for event in Parser::new_ext(source, options) {
+        renderer.event(event);
+    }
+
+    renderer.finish()
+}
+
+#[derive(Default)]
+struct Renderer {
+    width: usize,
+    ansi: bool,
+
+    output: String,
+
+    /// Text of the current block, before wrapping it.
+    inline: String,
+
+    /// Blocks where the current block is nested.
+    containers: Vec<Container>,
+
+    /// Counters for the lists. `None` for unordered lists.
+    lists: Vec<Option<u64>>,
+
+    /// Add an empty line before the next block.
+    pending_blank: bool,
+
+    /// URLs of the links found in the document.
+    links: Vec<String>,
+
+    /// Destinations of the links being rendered.
+    link_stack: Vec<String>,
+
+    code_block: Option<String>,
+
+    table: Option<Table>,
+}
+
+enum Container {
+    Quote,
+    Item { bullet: String, used: bool },
+}
+
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+    header_rows: usize,
+}
+
+impl Renderer {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+
+            Event::Text(text) => match &mut self.code_block {
+                Some(code) => code.push_str(&text),
+                None => self.inline.push_str(&text),
+            },
+
+            Event::Code(code) => {
+                if self.ansi {
+                    self.styled(CODE, &code);
+                } else {
+                    self.inline.push('`');
+                    self.inline.push_str(&code);
+                    self.inline.push('`');
+                }
+            }
+
+            Event::Html(html) => {
+                let html = html.trim().to_ascii_lowercase();
+                if html.starts_with("<br") {
+                    self.inline.push('\n');
+                }
+            }
+
+            Event::SoftBreak => self.inline.push(' '),
+
+            Event::HardBreak => self.inline.push('\n'),
+
+            Event::Rule => {
+                self.flush_inline();
+                self.begin_block();
+                let (first, _) = self.prefixes();
+                let rule_width = self.width.saturating_sub(first.display_width()).max(3);
+                self.output.push_str(&first);
+                self.output.push_str(&"-".repeat(rule_width));
+                self.output.push('\n');
+                self.pending_blank = true;
+            }
+
+            Event::FootnoteReference(name) => {
+                self.inline.push_str(&format!("[^{}]", name));
+            }
+
+            Event::TaskListMarker(checked) => {
+                self.inline.push_str(if checked { "[x] " } else { "[ ] " });
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::Heading(_) | Tag::FootnoteDefinition(_) => {
+                self.flush_inline();
+            }
+
+            Tag::BlockQuote => {
+                self.flush_inline();
+                self.containers.push(Container::Quote);
+            }
+
+            Tag::CodeBlock(_) => {
+                self.flush_inline();
+                self.code_block = Some(String::new());
+            }
+
+            Tag::List(start) => {
+                self.flush_inline();
+                self.lists.push(start);
+            }
+
+            Tag::Item => {
+                self.flush_inline();
+
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+
+                    _ => "• ".to_string(),
+                };
+
+                self.containers.push(Container::Item {
+                    bullet,
+                    used: false,
+                });
+            }
+
+            Tag::Table(alignments) => {
+                self.flush_inline();
+                self.table = Some(Table {
+                    alignments,
+                    rows: Vec::new(),
+                    header_rows: 0,
+                });
+            }
+
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(Vec::new());
+                }
+            }
+
+            Tag::TableCell => self.inline.clear(),
+
+            Tag::Emphasis => self.style_start(ITALIC),
+            Tag::Strong => self.style_start(BOLD),
+            Tag::Strikethrough => self.style_start(STRIKE),
+
+            Tag::Link(_, destination, _) => {
+                self.style_start(UNDERLINE);
+                self.link_stack.push(destination.to_string());
+            }
+
+            Tag::Image(..) => (),
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::FootnoteDefinition(_) => {
+                self.flush_inline();
+                self.pending_blank = true;
+            }
+
+            Tag::Heading(level) => {
+                let text = std::mem::take(&mut self.inline);
+                let text = match (self.ansi, level) {
+                    (true, 1) => format!("{}{}{}", BOLD.0, UNDERLINE.0, text),
+                    (true, _) => format!("{}{}", BOLD.0, text),
+                    (false, 1) | (false, 2) => text,
+                    (false, _) => format!("{} {}", "#".repeat(level as usize), text),
+                };
+
+                self.inline = text;
+                let text_width = self.flush_inline();
+
+                if self.ansi {
+                    self.output.insert_str(self.output.len() - 1, "\x1b[0m");
+                } else if level <= 2 {
+                    let (_, rest) = self.prefixes();
+                    let underline = if level == 1 { "=" } else { "-" };
+                    self.output.push_str(&rest);
+                    self.output.push_str(&underline.repeat(text_width));
+                    self.output.push('\n');
+                }
+
+                self.pending_blank = true;
+            }
+
+            Tag::BlockQuote => {
+                self.flush_inline();
+                self.containers.pop();
+                self.pending_blank = true;
+            }
+
+            Tag::CodeBlock(kind) => {
+                let code = self.code_block.take().unwrap_or_default();
+                self.write_code_block(&code, kind);
+                self.pending_blank = true;
+            }
+
+            Tag::List(_) => {
+                self.flush_inline();
+                self.lists.pop();
+                self.pending_blank = true;
+            }
+
+            Tag::Item => {
+                self.flush_inline();
+                self.containers.pop();
+            }
+
+            Tag::Table(_) => {
+                if let Some(table) = self.table.take() {
+                    self.write_table(table);
+                }
+
+                self.pending_blank = true;
+            }
+
+            Tag::TableHead => {
+                if let Some(table) = &mut self.table {
+                    table.header_rows = table.rows.len();
+                }
+            }
+
+            Tag::TableRow => (),
+
+            Tag::TableCell => {
+                let cell = std::mem::take(&mut self.inline);
+                if let Some(row) = self.table.as_mut().and_then(|t| t.rows.last_mut()) {
+                    row.push(cell.trim().to_string());
+                }
+            }
+
+            Tag::Emphasis => self.style_end(ITALIC),
+            Tag::Strong => self.style_end(BOLD),
+            Tag::Strikethrough => self.style_end(STRIKE),
+
+            Tag::Link(..) => {
+                self.style_end(UNDERLINE);
+
+                let destination = self.link_stack.pop().unwrap_or_default();
+                if !destination.is_empty() && !destination.starts_with('#') {
+                    let number = self.link_number(destination);
+                    self.inline.push_str(&format!("[{}]", number));
+                }
+            }
+
+            Tag::Image(..) => (),
+        }
+    }
+
+    fn style_start(&mut self, style: (&str, &str)) {
+        if self.ansi {
+            self.inline.push_str(style.0);
+        }
+    }
+
+    fn style_end(&mut self, style: (&str, &str)) {
+        if self.ansi {
+            self.inline.push_str(style.1);
+        }
+    }
+
+    fn styled(&mut self, style: (&str, &str), text: &str) {
+        self.style_start(style);
+        self.inline.push_str(text);
+        self.style_end(style);
+    }
+
+    /// Number of a link in the references list.
+    fn link_number(&mut self, destination: String) -> usize {
+        match self.links.iter().position(|l| *l == destination) {
+            Some(index) => index + 1,
+            None => {
+                self.links.push(destination);
+                self.links.len()
+            }
+        }
+    }
+
+    /// Prefixes for the first line and the rest of lines of the current
+    /// block.
+    fn prefixes(&mut self) -> (String, String) {
+        let mut first = String::new();
+        let mut rest = String::new();
+
+        for container in &mut self.containers {
+            match container {
+                Container::Quote => {
+                    first.push_str("│ ");
+                    rest.push_str("│ ");
+                }
+
+                Container::Item { bullet, used } => {
+                    let indent = " ".repeat(bullet.display_width());
+
+                    if *used {
+                        first.push_str(&indent);
+                    } else {
+                        first.push_str(bullet);
+                        *used = true;
+                    }
+
+                    rest.push_str(&indent);
+                }
+            }
+        }
+
+        (first, rest)
+    }
+
+    /// Write the empty line between blocks, if needed.
+    fn begin_block(&mut self) {
+        if self.pending_blank && !self.output.is_empty() {
+            let prefix: String = self
+                .containers
+                .iter()
+                .map(|c| match c {
+                    Container::Quote => "│",
+                    Container::Item { .. } => "",
+                })
+                .collect();
+
+            self.output.push_str(&prefix);
+            self.output.push('\n');
+        }
+
+        self.pending_blank = false;
+    }
+
+    /// Wrap the current inline text and write it to the output.
+    ///
+    /// Returns the width of the longest line, without prefixes.
+    fn flush_inline(&mut self) -> usize {
+        let text = std::mem::take(&mut self.inline);
+        if text.trim().is_empty() {
+            return 0;
+        }
+
+        self.begin_block();
+
+        let (first, rest) = self.prefixes();
+        let width = self.width.saturating_sub(rest.display_width()).max(10);
+
+        let mut max_width = 0;
+        let mut first_line = true;
+
+        for line in text.trim().split('\n') {
+            for wrapped in wrap(line.trim(), width) {
+                let prefix = if first_line { &first } else { &rest };
+                first_line = false;
+
+                max_width = max_width.max(wrapped.display_width());
+
+                self.output.push_str(prefix);
+                self.output.push_str(&wrapped);
+                self.output.push('\n');
+            }
+        }
+
+        max_width
+    }
+
+    fn write_code_block(&mut self, code: &str, kind: CodeBlockKind) {
+        self.begin_block();
+
+        let indent = match kind {
+            CodeBlockKind::Indented => "    ",
+            CodeBlockKind::Fenced(_) => "  ",
+        };
+
+        let (_, rest) = self.prefixes();
+        for line in code.trim_end_matches('\n').split('\n') {
+            self.output.push_str(&rest);
+            self.output.push_str(indent);
+
+            if self.ansi {
+                self.output.push_str(CODE.0);
+                self.output.push_str(line);
+                self.output.push_str(CODE.1);
+            } else {
+                self.output.push_str(line);
+            }
+
+            self.output.push('\n');
+        }
+    }
+
+    fn write_table(&mut self, table: Table) {
+        self.begin_block();
+
+        let columns = table.rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        for row in &table.rows {
+            for (cell, width) in row.iter().zip(widths.iter_mut()) {
+                *width = (*width).max(cell.display_width());
+            }
+        }
+
+        let (_, prefix) = self.prefixes();
+
+        for (index, row) in table.rows.iter().enumerate() {
+            if index == table.header_rows && index > 0 {
+                let separator: Vec<_> = widths.iter().map(|w| "-".repeat(*w)).collect();
+                self.output.push_str(&prefix);
+                self.output.push_str(&separator.join("-+-"));
+                self.output.push('\n');
+            }
+
+            let mut line = String::new();
+            for (column, width) in widths.iter().enumerate() {
+                if column > 0 {
+                    line.push_str(" | ");
+                }
+
+                let cell = row.get(column).map(|c| c.as_str()).unwrap_or_default();
+                let padding = width - cell.display_width();
+                let alignment = table.alignments.get(column).copied();
+
+                let (left, right) = match alignment {
+                    Some(Alignment::Right) => (padding, 0),
+                    Some(Alignment::Center) => (padding / 2, padding - padding / 2),
+                    _ => (0, padding),
+                };
+
+                line.push_str(&" ".repeat(left));
+                line.push_str(cell);
+                line.push_str(&" ".repeat(right));
+            }
+
+            self.output.push_str(&prefix);
+            self.output.push_str(line.trim_end());
+            self.output.push('\n');
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush_inline();
+
+        if !self.links.is_empty() {
+            self.output.push('\n');
+            for (index, link) in self.links.iter().enumerate() {
+                self.output.push_str(&format!("[{}] {}\n", index + 1, link));
+            }
+        }
+
+        self.output
+    }
+}
+
+/// Wrap `text` to lines of `width` cells.
+///
+/// `textwrap` is not used because it counts the ANSI escape sequences of the
+/// styles as visible characters, so styled paragraphs were wrapped too early.
+/// Here, words are measured with `display_width`, which ignores escape
+/// sequences and counts wide characters as two cells. Words longer than
+/// `width` are not split.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = 0;
+
+    for word in text.split(' ').filter(|w| !w.is_empty()) {
+        let word_width = word.display_width();
+
+        if line_width > 0 && line_width + 1 + word_width > width {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0;
+        }
+
+        if line_width > 0 {
+            line.push(' ');
+            line_width += 1;
+        }
+
+        line.push_str(word);
+        line_width += word_width;
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[test]
+fn render_plain_text() {
+    let source = "\
+# Quick reference
+
+Maintained by [the Docker Community](https://github.com/docker-library/redis),
+see the [docs](https://docs.docker.com/) and **this** `code`.
+
+<!-- comment -->
+
+- First item
+- Second item
+  1. Nested
+
+```
+$ docker run redis
+```
+
+| Tag | Size |
+|-----|-----:|
+| 7   | 30MB |
+";
+
+    let expected = "\
+Quick reference
+===============
+
+Maintained by the Docker Community[1], see the
+docs[2] and this `code`.
+
+• First item
+• Second item
+  1. Nested
+
+  $ docker run redis
+
+Tag | Size
+----+-----
+7   | 30MB
+
+[1] https://github.com/docker-library/redis
+[2] https://docs.docker.com/
+";
+
+    assert_eq!(render(source, 50, false), expected);
+}
+
+#[test]
+fn render_ansi_styles() {
+    let output = render("Some **bold** text", 80, true);
+    assert_eq!(output, "Some \x1b[1mbold\x1b[22m text\n");
+    assert_eq!(output.display_width(), 14);
+}
+
+#[test]
+fn wrap_ansi_styles() {
+    let output = render("Some **bold** and *italic* text in a paragraph", 20, true);
+    let lines: Vec<_> = output.lines().map(|l| l.strip_ansi()).collect();
+    assert_eq!(lines, ["Some bold and italic", "text in a paragraph"]);
+}
+
+#[test]
+fn wrap_escape_sequences() {
+    let text = "\x1b[1mbold\x1b[22m words \x1b[4mwith\x1b[24m styles";
+    assert_eq!(
+        wrap(text, 11),
+        ["\x1b[1mbold\x1b[22m words", "\x1b[4mwith\x1b[24m styles"]
+    );
+}
+
+#[test]
+fn wrap_wide_characters() {
+    assert_eq!(wrap("日本語 の テキスト", 10), ["日本語 の", "テキスト"]);
+}
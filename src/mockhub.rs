@@ -0,0 +1,383 @@
+//! Implementation of the 'mock-server' command.
+//!
+//! The server implements the endpoints of Docker Hub used by hdcquery, with
+//! the repositories from a seed file. Combined with `--hub-url`, all commands
+//! can be executed without network access.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use serde_json::{json, Value};
+
+use crate::options::MockServerOptions;
+
+const SEARCH_PATH: &str = "/api/content/v1/products/search";
+
+const REPOSITORIES_PATH: &str = "/v2/repositories/";
+
+const DEFAULT_SEARCH_PAGE_SIZE: usize = 25;
+
+const DEFAULT_TAGS_PAGE_SIZE: usize = 10;
+
+/// Seconds sent in the `Retry-After` header of throttled requests.
+const RETRY_AFTER: &str = "1";
+
+#[derive(serde::Deserialize, Debug)]
+struct Seed {
+    repositories: Vec<SeedRepository>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct SeedRepository {
+    namespace: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    full_description: String,
+    #[serde(default)]
+    star_count: u64,
+    #[serde(default)]
+    pull_count: u64,
+    #[serde(default)]
+    is_automated: bool,
+    last_updated: Option<DateTime<Utc>>,
+
+    /// Tags, in the same format returned by Docker Hub.
+    #[serde(default)]
+    tags: Vec<Value>,
+}
+
+impl SeedRepository {
+    fn slug(&self) -> String {
+        if self.namespace == "library" {
+            self.name.clone()
+        } else {
+            format!("{}/{}", self.namespace, self.name)
+        }
+    }
+}
+
+struct MockHub {
+    seed: Seed,
+    throttle_every: Option<usize>,
+    requests: AtomicUsize,
+}
+
+/// Response of the mock server.
+struct MockResponse {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Value,
+}
+
+impl MockResponse {
+    fn ok(body: Value) -> MockResponse {
+        MockResponse {
+            status: 200,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    fn not_found() -> MockResponse {
+        MockResponse {
+            status: 404,
+            headers: Vec::new(),
+            body: json!({ "message": "object not found" }),
+        }
+    }
+}
+
+pub async fn run(options: MockServerOptions) -> anyhow::Result<()> {
+    let seed = std::fs::read(&options.seed)?;
+    let hub = Arc::new(MockHub {
+        seed: serde_json::from_slice(&seed)?,
+        throttle_every: options.throttle_every,
+        requests: AtomicUsize::new(0),
+    });
+
+    let addr: SocketAddr = options.listen.parse()?;
+
+    let make_service = make_service_fn(move |_| {
+        let hub = Arc::clone(&hub);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let hub = Arc::clone(&hub);
+                async move { Ok::<_, Infallible>(hub.handle(request)) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    eprintln!("Listening on http://{}", server.local_addr());
+
+    server.await?;
+    Ok(())
+}
+
+impl MockHub {
+    fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let host = request
+            .headers()
+            .get(hyper::header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("localhost");
+
+        let path_and_query = request
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+
+        let response = self.respond(host, path_and_query);
+
+        let mut builder = Response::builder()
+            .status(response.status)
+            .header("Content-Type", "application/json");
+
+        for (name, value) in response.headers {
+            builder = builder.header(name, value);
+        }
+
+        builder
+            .body(Body::from(response.body.to_string()))
+            .expect("valid response")
+    }
+
+    fn respond(&self, host: &str, path_and_query: &str) -> MockResponse {
+        let count = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.throttle_every.and_then(|n| count.checked_rem(n)) == Some(0) {
+            return MockResponse {
+                status: 429,
+                headers: vec![("Retry-After", RETRY_AFTER.to_string())],
+                body: json!({ "message": "too many requests" }),
+            };
+        }
+
+        let url = match reqwest::Url::parse(&format!("http://{}{}", host, path_and_query)) {
+            Ok(url) => url,
+            Err(_) => return MockResponse::not_found(),
+        };
+
+        let query = |name: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.into_owned())
+        };
+
+        let number = |name: &str, default: usize| {
+            query(name)
+                .and_then(|v| v.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(default)
+        };
+
+        let path = url.path().trim_end_matches('/');
+
+        if path == SEARCH_PATH {
+            let terms = query("q").unwrap_or_default();
+            let page = number("page", 1);
+            let page_size = number("page_size", DEFAULT_SEARCH_PAGE_SIZE);
+            return self.search(&terms, page, page_size);
+        }
+
+        let components: Vec<_> = match path.strip_prefix(REPOSITORIES_PATH) {
+            Some(rest) => rest.split('/').collect(),
+            None => return MockResponse::not_found(),
+        };
+
+        let repository = match self.seed.repositories.iter().find(|r| {
+            components.len() >= 2 && r.namespace == components[0] && r.name == components[1]
+        }) {
+            Some(r) => r,
+            None => return MockResponse::not_found(),
+        };
+
+        match components.len() {
+            2 => MockResponse::ok(repository_json(repository)),
+
+            3 if components[2] == "tags" => {
+                let page = number("page", 1);
+                let page_size = number("page_size", DEFAULT_TAGS_PAGE_SIZE);
+                let name = query("name");
+                tags_page(&url, repository, name.as_deref(), page, page_size)
+            }
+
+            _ => MockResponse::not_found(),
+        }
+    }
+
+    /// Repositories with the terms in their name or description.
+    fn search(&self, terms: &str, page: usize, page_size: usize) -> MockResponse {
+        let terms = terms.to_lowercase();
+        let found: Vec<_> = self
+            .seed
+            .repositories
+            .iter()
+            .filter(|r| r.slug().contains(&terms) || r.description.to_lowercase().contains(&terms))
+            .collect();
+
+        let summaries: Vec<_> = found
+            .iter()
+            .skip((page - 1).saturating_mul(page_size))
+            .take(page_size)
+            .map(|r| {
+                json!({
+                    "name": r.slug(),
+                    "slug": r.slug(),
+                    "updated_at": r.last_updated.unwrap_or_else(Utc::now),
+                    "pull_count": approximate_count(r.pull_count),
+                    "star_count": r.star_count,
+                    "short_description": r.description,
+                    "filter_type": if r.namespace == "library" { "official" } else { "community" },
+                })
+            })
+            .collect();
+
+        MockResponse::ok(json!({
+            "count": found.len(),
+            "page": page,
+            "page_size": page_size,
+            "summaries": summaries,
+        }))
+    }
+}
+
+fn repository_json(repository: &SeedRepository) -> Value {
+    json!({
+        "namespace": repository.namespace,
+        "name": repository.name,
+        "description": repository.description,
+        "full_description": repository.full_description,
+        "star_count": repository.star_count,
+        "pull_count": repository.pull_count,
+        "is_automated": repository.is_automated,
+        "last_updated": repository.last_updated,
+    })
+}
+
+/// A page of tags, with a `next` link if there are more pages.
+///
+/// Like Docker Hub, pages after the last one are `404` responses.
+fn tags_page(
+    url: &reqwest::Url,
+    repository: &SeedRepository,
+    name: Option<&str>,
+    page: usize,
+    page_size: usize,
+) -> MockResponse {
+    let tags: Vec<_> = repository
+        .tags
+        .iter()
+        .filter(|tag| match (name, tag["name"].as_str()) {
+            (Some(name), Some(tag)) => tag.contains(name),
+            _ => true,
+        })
+        .collect();
+
+    let start = (page - 1).saturating_mul(page_size);
+    if page > 1 && start >= tags.len() {
+        return MockResponse::not_found();
+    }
+
+    let ordering = url
+        .query_pairs()
+        .find(|(key, _)| key == "ordering")
+        .map(|(_, value)| value.into_owned());
+
+    let link = |page: usize| {
+        let mut link = url.clone();
+        link.query_pairs_mut()
+            .clear()
+            .append_pair("page", &page.to_string())
+            .append_pair("page_size", &page_size.to_string());
+
+        if let Some(name) = name {
+            link.query_pairs_mut().append_pair("name", name);
+        }
+
+        if let Some(ordering) = &ordering {
+            link.query_pairs_mut().append_pair("ordering", ordering);
+        }
+
+        link.to_string()
+    };
+
+    let next = if start + page_size < tags.len() {
+        Some(link(page + 1))
+    } else {
+        None
+    };
+
+    let previous = if page > 1 { Some(link(page - 1)) } else { None };
+
+    let results: Vec<_> = tags.iter().skip(start).take(page_size).collect();
+
+    MockResponse::ok(json!({
+        "count": tags.len(),
+        "next": next,
+        "previous": previous,
+        "results": results,
+    }))
+}
+
+/// Pull count as reported by the search API, like `"10M+"`.
+fn approximate_count(count: u64) -> String {
+    let units = [(1_000_000_000, "B"), (1_000_000, "M"), (1_000, "K")];
+
+    for (size, unit) in units.iter() {
+        if count >= *size {
+            return format!("{}{}+", count / size, unit);
+        }
+    }
+
+    count.to_string()
+}
+
+#[test]
+fn serve_seed_data() {
+    let seed = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/mockhub.json")).unwrap();
+
+    let hub = MockHub {
+        seed: serde_json::from_slice(&seed).unwrap(),
+        throttle_every: Some(5),
+        requests: AtomicUsize::new(0),
+    };
+
+    let get = |path: &str| hub.respond("mock:5080", path);
+
+    let response = get("/api/content/v1/products/search?q=redis&page=1&page_size=1");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body["count"], 2);
+    assert_eq!(response.body["summaries"][0]["slug"], "redis");
+    assert_eq!(response.body["summaries"][0]["pull_count"], "1B+");
+
+    let response = get("/v2/repositories/library/redis/");
+    assert_eq!(response.body["name"], "redis");
+
+    let response = get("/v2/repositories/library/redis/tags/?page_size=2");
+    assert_eq!(response.body["count"], 3);
+    assert_eq!(
+        response.body["next"],
+        "http://mock:5080/v2/repositories/library/redis/tags/?page=2&page_size=2"
+    );
+
+    assert_eq!(
+        get("/v2/repositories/library/redis/tags/?page=3&page_size=2").status,
+        404
+    );
+
+    // Fifth request is throttled.
+    let response = get("/v2/repositories/library/redis/");
+    assert_eq!(response.status, 429);
+    assert_eq!(response.headers, [("Retry-After", "1".to_string())]);
+
+    assert_eq!(get("/v2/repositories/library/nope/").status, 404);
+    assert_eq!(approximate_count(512_300), "512K+");
+}
@@ -1,12 +1,118 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::prelude::*;
+use regex::Regex;
+
+use crate::credentials;
+use crate::platform::Platform;
+use crate::reference::Reference;
+use crate::tags::View;
+
 #[derive(Debug, gumdrop::Options)]
 pub struct Options {
     #[options(help = "print help message")]
     pub help: bool,
 
+    #[options(
+        no_short,
+        help = "Write the output directly to stdout, without a pager"
+    )]
+    pub no_pager: bool,
+
+    #[options(
+        count,
+        short = "v",
+        help = "Log HTTP requests to stderr (use -vv to include response bodies)"
+    )]
+    pub trace_http: u32,
+
+    #[options(
+        no_short,
+        meta = "DIR",
+        help = "Save all HTTP requests and responses in a directory"
+    )]
+    pub record: Option<PathBuf>,
+
+    #[options(
+        no_short,
+        meta = "DIR",
+        help = "Read HTTP responses from a directory created with --record"
+    )]
+    pub replay: Option<PathBuf>,
+
+    #[options(
+        no_short,
+        meta = "URL",
+        help = "Send requests for hub.docker.com to another server"
+    )]
+    pub hub_url: Option<String>,
+
+    #[options(
+        no_short,
+        meta = "URL",
+        help = "Send requests through a proxy, instead of HTTPS_PROXY"
+    )]
+    pub proxy: Option<String>,
+
+    #[options(
+        no_short,
+        meta = "FILE",
+        help = "Trust the CA certificates in a PEM file"
+    )]
+    pub cacert: Option<PathBuf>,
+
+    #[options(no_short, help = "Don't verify TLS certificates")]
+    pub insecure: bool,
+
+    #[options(
+        no_short,
+        meta = "SECS",
+        help = "Maximum time for a request (default: 30)"
+    )]
+    pub timeout: Option<u64>,
+
+    #[options(
+        no_short,
+        meta = "SECS",
+        help = "Maximum time to connect to a server (default: 10)"
+    )]
+    pub connect_timeout: Option<u64>,
+
+    #[options(
+        no_short,
+        meta = "SECS",
+        help = "Reuse cached responses newer than SECS (default: 0, no cache)"
+    )]
+    pub cache_ttl: Option<u64>,
+
+    #[options(
+        no_short,
+        meta = "DIR",
+        help = "Directory for cached responses (default: $XDG_CACHE_HOME/hdcquery/http)"
+    )]
+    pub cache_dir: Option<PathBuf>,
+
+    #[options(
+        no_short,
+        meta = "SOURCE",
+        help = "Credentials for other registries: none or docker (default: none)"
+    )]
+    pub credentials: Option<credentials::Source>,
+
+    #[options(
+        no_short,
+        meta = "FORMAT",
+        help = "Output format: text or json (default: text)"
+    )]
+    pub output: Option<OutputFormat>,
+
     #[options(command)]
     pub command: Option<Command>,
 }
 
+// The value is created only once, so the size of the variants is not relevant.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, gumdrop::Options)]
 pub enum Command {
     #[options(help = "search for repositories")]
@@ -17,6 +123,116 @@ pub enum Command {
 
     #[options(help = "show repository tags")]
     Tags(TagsOptions),
+
+    #[options(help = "start a mock server for the Docker Hub API")]
+    MockServer(MockServerOptions),
+
+    #[options(help = "manage the configuration file")]
+    Config(ConfigOptions),
+}
+
+/// Global options that take a value, so the value is not confused with the
+/// name of a command.
+const VALUE_OPTIONS: &[&str] = &[
+    "--record",
+    "--replay",
+    "--hub-url",
+    "--proxy",
+    "--cacert",
+    "--timeout",
+    "--connect-timeout",
+    "--cache-ttl",
+    "--cache-dir",
+    "--credentials",
+    "--output",
+];
+
+/// Position of the command name in the arguments, skipping global options.
+pub fn command_index(args: &[String]) -> Option<usize> {
+    let mut index = 0;
+    while let Some(arg) = args.get(index) {
+        if arg == "--" {
+            return None;
+        }
+
+        if !arg.starts_with('-') {
+            return Some(index);
+        }
+
+        index += if VALUE_OPTIONS.contains(&arg.as_str()) {
+            2
+        } else {
+            1
+        };
+    }
+
+    None
+}
+
+/// Names of the built-in commands.
+pub fn builtin_commands() -> Vec<&'static str> {
+    <Options as gumdrop::Options>::command_list()
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .collect()
+}
+
+/// Parse the arguments of the program (without the program name), like
+/// `parse_args_default_or_exit`. Aliases are listed in `--help`.
+pub fn parse_args_or_exit(args: &[String], aliases: &[(&str, &str)]) -> Options {
+    use gumdrop::Options as _;
+
+    let program = std::env::args().next().unwrap_or_default();
+
+    let options = match Options::parse_args_default(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}: {}", program, e);
+            std::process::exit(2);
+        }
+    };
+
+    if !options.help_requested() {
+        return options;
+    }
+
+    // Show the usage of the last command in the arguments.
+    let mut command: &dyn gumdrop::Options = &options;
+    let mut command_str = String::new();
+    while let Some(subcommand) = command.command() {
+        command = subcommand;
+        if let Some(name) = subcommand.command_name() {
+            command_str.push(' ');
+            command_str.push_str(name);
+        }
+    }
+
+    println!("Usage: {}{} [OPTIONS]", program, command_str);
+    println!();
+    println!("{}", command.self_usage());
+
+    if let Some(commands) = command.self_command_list() {
+        println!();
+        println!("Available commands:");
+        println!("{}", commands);
+    }
+
+    if command_str.is_empty() && !aliases.is_empty() {
+        let width = aliases
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+
+        println!();
+        println!("Aliases:");
+        for (name, command) in aliases {
+            println!("  {:width$}  {}", name, command, width = width);
+        }
+    }
+
+    std::process::exit(0);
 }
 
 #[derive(Debug, gumdrop::Options)]
@@ -27,23 +243,112 @@ pub struct SearchOptions {
     #[options(help = "URL to send search requests")]
     pub search_url: Option<String>,
 
+    #[options(no_short, help = "Only show official images")]
+    pub official: bool,
+
+    #[options(no_short, help = "Only show images from verified publishers")]
+    pub verified: bool,
+
+    #[options(help = "Filter by operating system")]
+    pub operating_system: Option<String>,
+
+    #[options(help = "Filter by architecture")]
+    pub architecture: Option<String>,
+
+    #[options(help = "Filter by category")]
+    pub category: Option<String>,
+
+    #[options(no_short, help = "Sort results by pulls, stars or updated")]
+    pub sort: Option<SearchSort>,
+
+    #[options(no_short, help = "Comma-separated list of columns to show")]
+    pub columns: Option<String>,
+
     #[options(free)]
     pub terms: Vec<String>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchSort {
+    Pulls,
+    Stars,
+    Updated,
+}
+
+impl FromStr for SearchSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pulls" => Ok(SearchSort::Pulls),
+            "stars" => Ok(SearchSort::Stars),
+            "updated" => Ok(SearchSort::Updated),
+            _ => Err(format!("invalid sort key {:?}", s)),
+        }
+    }
+}
+
+/// Format of the results in the `search`, `show` and `tags` commands.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Tables and text for the terminal.
+    #[default]
+    Text,
+
+    /// One JSON object per line, with the data from the API.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("invalid output format {:?}", s)),
+        }
+    }
+}
+
 #[derive(Debug, gumdrop::Options)]
 pub struct ShowOptions {
     #[options(help = "Only show full description")]
     pub only_description: bool,
 
+    #[options(help = "Show full description as Markdown, without rendering it")]
+    pub raw: bool,
+
+    #[options(
+        help = "Number of repositories to download concurrently",
+        default = "4"
+    )]
+    pub jobs: usize,
+
     #[options(free)]
-    pub repositories: Vec<String>,
+    pub repositories: Vec<Reference>,
 }
 
 #[derive(Debug, gumdrop::Options)]
 pub struct TagsOptions {
-    #[options(help = "Limit the number of results", default = "30")]
-    pub limit: usize,
+    #[options(help = "Limit the number of results for every repository (default: 30)")]
+    pub limit: Option<usize>,
+
+    #[options(
+        no_short,
+        meta = "LIMIT",
+        help = "Limit the number of results for all repositories"
+    )]
+    pub total_limit: Option<usize>,
+
+    #[options(no_short, help = "Show all results, ignoring --limit")]
+    pub all: bool,
+
+    #[options(
+        help = "Number of repositories to download concurrently",
+        default = "4"
+    )]
+    pub jobs: usize,
 
     #[options(help = "Show image digest")]
     pub digest: bool,
@@ -57,6 +362,280 @@ pub struct TagsOptions {
     #[options(help = "Filter by operating system and architecture of this machine")]
     pub current_machine: bool,
 
+    #[options(
+        no_short,
+        meta = "OS/ARCH[/VARIANT]",
+        help = "Filter by platform (like linux/arm/v7, or all for no filter)"
+    )]
+    pub platform: Option<PlatformArg>,
+
+    #[options(no_short, help = "Show attestation manifests (SBOM and provenance)")]
+    pub attestations: bool,
+
+    #[options(no_short, help = "Comma-separated list of columns to show")]
+    pub columns: Option<String>,
+
+    #[options(help = "Show one row per tag, with the list of its platforms")]
+    pub group: bool,
+
+    #[options(help = "Show every tag, followed by its images")]
+    pub tree: bool,
+
+    #[options(no_short, help = "Show tags with the same images in a single row")]
+    pub aliases: bool,
+
+    #[options(
+        no_short,
+        meta = "VIEW",
+        help = "Show the tags as images, group, tree or aliases"
+    )]
+    pub view: Option<View>,
+
+    #[options(
+        no_short,
+        meta = "WHEN",
+        help = "Only tags pushed after a date or an age (like 2024-01-01 or 30d)"
+    )]
+    pub since: Option<TimeArg>,
+
+    #[options(
+        no_short,
+        meta = "WHEN",
+        help = "Only tags pushed before a date or an age (like 2024-01-01 or 30d)"
+    )]
+    pub before: Option<TimeArg>,
+
+    #[options(
+        short = "m",
+        long = "match",
+        meta = "REGEX",
+        help = "Only tags with a name matching a regular expression"
+    )]
+    pub name_match: Option<Regex>,
+
+    #[options(
+        no_short,
+        meta = "REGEX",
+        help = "Exclude tags with a name matching a regular expression"
+    )]
+    pub exclude: Option<Regex>,
+
+    #[options(
+        no_short,
+        meta = "SIZE",
+        help = "Only images of at least this size (like 100MB)"
+    )]
+    pub min_size: Option<SizeArg>,
+
+    #[options(
+        no_short,
+        meta = "SIZE",
+        help = "Only images of at most this size (like 1GiB)"
+    )]
+    pub max_size: Option<SizeArg>,
+
     #[options(free)]
-    pub repositories: Vec<String>,
+    pub repositories: Vec<Reference>,
+}
+
+#[derive(Debug, gumdrop::Options)]
+pub struct MockServerOptions {
+    #[options(help = "Address to listen for connections", default = "127.0.0.1:5080")]
+    pub listen: String,
+
+    #[options(
+        required,
+        meta = "FILE",
+        help = "JSON file with the repositories to serve"
+    )]
+    pub seed: PathBuf,
+
+    #[options(
+        no_short,
+        meta = "N",
+        help = "Respond with a 429 status to one of every N requests"
+    )]
+    pub throttle_every: Option<usize>,
+}
+
+#[derive(Debug, gumdrop::Options)]
+pub struct ConfigOptions {
+    #[options(command)]
+    pub command: Option<ConfigCommand>,
+}
+
+#[derive(Debug, gumdrop::Options)]
+pub enum ConfigCommand {
+    #[options(help = "print the value of a key")]
+    Get(ConfigGetOptions),
+
+    #[options(help = "change the value of a key")]
+    Set(ConfigSetOptions),
+
+    #[options(help = "print all keys in the configuration file")]
+    List(ConfigListOptions),
+
+    #[options(help = "print the keys accepted in the configuration file")]
+    Keys(ConfigListOptions),
+}
+
+#[derive(Debug, gumdrop::Options)]
+pub struct ConfigGetOptions {
+    #[options(free, required)]
+    pub key: String,
+}
+
+#[derive(Debug, gumdrop::Options)]
+pub struct ConfigSetOptions {
+    #[options(free, required)]
+    pub key: String,
+
+    #[options(free, required)]
+    pub value: String,
+}
+
+#[derive(Debug, gumdrop::Options)]
+pub struct ConfigListOptions {}
+
+/// Point in time, given as a date (`2024-01-01`), as a timestamp
+/// (`2024-01-01T10:00:00Z`), or as an age relative to the current time
+/// (`30d`).
+///
+/// Ages are a number followed by a unit: `s`, `m` (minutes), `h`, `d` or
+/// `w`.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeArg(pub DateTime<Utc>);
+
+impl FromStr for TimeArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%F") {
+            return Ok(TimeArg(Utc.from_utc_date(&date).and_hms(0, 0, 0)));
+        }
+
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(TimeArg(time.with_timezone(&Utc)));
+        }
+
+        let invalid = || format!("invalid date or age {:?}", s);
+
+        let unit_pos = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let amount: i64 = s[..unit_pos].parse().map_err(|_| invalid())?;
+
+        let unit_millis = match &s[unit_pos..] {
+            "s" => 1_000,
+            "m" => 60_000,
+            "h" => 3_600_000,
+            "d" => 86_400_000,
+            "w" => 604_800_000,
+            _ => return Err(invalid()),
+        };
+
+        // Ages too large for a `DateTime` are rejected, instead of panicking.
+        let age = amount.checked_mul(unit_millis).ok_or_else(invalid)?;
+        Utc::now()
+            .checked_sub_signed(chrono::Duration::milliseconds(age))
+            .map(TimeArg)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Platform filter, given as a platform (`linux/arm/v7`), or as `all` to
+/// show every platform.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlatformArg {
+    All,
+    Only(Platform),
+}
+
+impl FromStr for PlatformArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(PlatformArg::All),
+            _ => s.parse().map(PlatformArg::Only),
+        }
+    }
+}
+
+/// Size in bytes, like `100MB` or `1.5GiB`.
+///
+/// Units without `B` (like `100M`) are binary units, the same used in the
+/// output of the `tags` command.
+#[derive(Debug, Clone, Copy)]
+pub struct SizeArg(pub u64);
+
+impl FromStr for SizeArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid size {:?}", s);
+
+        let unit_pos = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+
+        let number: f64 = s[..unit_pos].parse().map_err(|_| invalid())?;
+
+        let multiplier = match s[unit_pos..].trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+            "k" | "kib" => 1 << 10,
+            "m" | "mib" => 1 << 20,
+            "g" | "gib" => 1 << 30,
+            _ => return Err(invalid()),
+        };
+
+        Ok(SizeArg((number * multiplier as f64) as u64))
+    }
+}
+
+#[test]
+fn parse_filter_arguments() {
+    let date = |s: &str| s.parse::<TimeArg>().map(|t| t.0);
+
+    assert_eq!(date("2024-01-01"), Ok(Utc.ymd(2024, 1, 1).and_hms(0, 0, 0)));
+
+    assert_eq!(
+        date("2024-01-01T10:20:30+02:00"),
+        Ok(Utc.ymd(2024, 1, 1).and_hms(8, 20, 30))
+    );
+
+    let time = date("30d").unwrap();
+    let age = Utc::now() - time;
+    assert_eq!(age.num_days(), 30);
+
+    assert!(date("30").is_err());
+    assert!(date("30y").is_err());
+    assert!(date("d").is_err());
+    assert!(date("999999999999d").is_err());
+    assert!(date("99999999999d").is_err());
+    assert!(date("9999999999999999999s").is_err());
+
+    let size = |s: &str| s.parse::<SizeArg>().map(|s| s.0);
+
+    assert_eq!(size("100"), Ok(100));
+    assert_eq!(size("100MB"), Ok(100_000_000));
+    assert_eq!(size("1.5GiB"), Ok(1_610_612_736));
+    assert_eq!(size("2k"), Ok(2048));
+    assert!(size("big").is_err());
+    assert!(size("10PB").is_err());
+}
+
+#[test]
+fn value_options_are_complete() {
+    // Options with a value have a metavariable in the usage text, like
+    // `--record DIR`.
+    for line in <Options as gumdrop::Options>::usage().lines() {
+        let mut words = line.split_whitespace().skip_while(|w| w.ends_with(','));
+        if let (Some(option), Some(meta)) = (words.next(), words.next()) {
+            if meta.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+                assert!(VALUE_OPTIONS.contains(&option), "missing {}", option);
+            }
+        }
+    }
 }
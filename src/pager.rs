@@ -1,28 +1,195 @@
 //! Run an external program as a pager (like "less")
+//!
+//! If the external program can't be executed, the output is shown with a
+//! built-in pager.
+//!
+//! The pager will receive the following environment variables:
+//!
+//! * `HDCQUERY_COMMAND`: name of the command, like `"show"`.
+//! * `HDCQUERY_QUERY`: terms of the search, for the `search` command.
+//! * `HDCQUERY_REPOSITORY`: name of the repository, like `"library/rust"`.
+//!   If there are multiple repositories, they are separated by spaces.
+//! * `HDCQUERY_VERSION`: version of hdcquery.
 
 use std::env;
-use std::process::{Command, Stdio};
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use once_cell::sync::OnceCell;
+
+mod builtin;
 
 pub const PAGER_ENV: &str = "HDC_PAGER";
 
+/// Value for `$HDC_PAGER` to use the built-in pager.
+pub const BUILTIN_PAGER: &str = "builtin";
+
 const DEFAULT_PAGER: &str = if cfg!(windows) { "more" } else { "pager -F" };
 
-pub fn command(repository: Option<&str>) -> Option<Command> {
-    if !atty::is(atty::Stream::Stdout) {
-        return None;
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Pager from the configuration file, used if `$HDC_PAGER` is not set.
+static CONFIGURED: OnceCell<String> = OnceCell::new();
+
+/// Write the output of every command directly to stdout.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Set the pager to use if `$HDC_PAGER` is not set.
+pub fn set_command(command: String) {
+    let _ = CONFIGURED.set(command);
+}
+
+/// Information about the command that sends its output to the pager.
+#[derive(Default)]
+pub struct Context<'a> {
+    pub command: &'a str,
+    pub query: Option<&'a str>,
+    pub repository: Option<&'a str>,
+}
+
+/// Destination for the output of a command.
+pub struct Output {
+    target: Target,
+}
+
+enum Target {
+    Stdout(io::Stdout),
+    External(Child, ChildStdin),
+    Builtin(Vec<u8>),
+}
+
+/// Write the output directly to stdout.
+pub fn stdout() -> Output {
+    Output {
+        target: Target::Stdout(io::stdout()),
     }
+}
 
-    let pager_var = env::var(PAGER_ENV);
-    let pager_args = pager_var.as_deref().unwrap_or(DEFAULT_PAGER);
+/// Open a pager to write the output of a command.
+///
+/// If stdout is not a terminal, or the pager is disabled, the output is
+/// written directly to stdout.
+pub fn open(context: &Context) -> Output {
+    let target = match command(context) {
+        Some(mut cmd) => match cmd.spawn() {
+            Ok(mut child) => {
+                let stdin = child.stdin.take().expect("stdin expected for pager");
+                Target::External(child, stdin)
+            }
 
-    let mut pager_args = match shell_words::split(pager_args) {
-        Ok(words) => words.into_iter(),
-        Err(e) => {
-            eprintln!("Failed to parse $HDC_PAGER: {:?}", e);
-            return None;
+            // A missing default pager is expected in some systems, so the
+            // warning is only shown for a pager given by the user.
+            Err(e) => {
+                if configured().is_some() {
+                    eprintln!("Failed to execute pager ({}). Using built-in pager.", e);
+                }
+
+                Target::Builtin(Vec::new())
+            }
+        },
+
+        None if !DISABLED.load(Ordering::Relaxed)
+            && !is_stdout_requested()
+            && atty::is(atty::Stream::Stdout) =>
+        {
+            Target::Builtin(Vec::new())
         }
+
+        None => Target::Stdout(io::stdout()),
     };
 
+    Output { target }
+}
+
+impl Output {
+    /// Check if the output can contain ANSI escape sequences.
+    pub fn supports_ansi(&self) -> bool {
+        match self.target {
+            Target::Stdout(_) => false,
+            Target::External(..) => supports_ansi(),
+            Target::Builtin(_) => true,
+        }
+    }
+
+    /// Wait until the user closes the pager.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        match std::mem::replace(&mut self.target, Target::Stdout(io::stdout())) {
+            Target::Stdout(mut stdout) => stdout.flush()?,
+            Target::External(child, stdin) => wait_external(child, stdin),
+            Target::Builtin(buffer) => builtin::run(&String::from_utf8_lossy(&buffer))?,
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Output {
+    /// If the command fails before calling `finish`, the external pager
+    /// shows the output written so far.
+    fn drop(&mut self) {
+        if let Target::External(..) = self.target {
+            if let Target::External(child, stdin) =
+                std::mem::replace(&mut self.target, Target::Stdout(io::stdout()))
+            {
+                wait_external(child, stdin);
+            }
+        }
+    }
+}
+
+fn wait_external(mut child: Child, stdin: ChildStdin) {
+    // Close the pager's input
+    drop(stdin);
+    let _ = child.wait();
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.target {
+            Target::Stdout(stdout) => stdout.write(buf),
+
+            // If the user closes the pager before reading the whole output,
+            // the rest of the output is discarded.
+            Target::External(_, stdin) => match stdin.write(buf) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(buf.len()),
+                result => result,
+            },
+
+            Target::Builtin(buffer) => buffer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.target {
+            Target::Stdout(stdout) => stdout.flush(),
+            Target::External(_, stdin) => match stdin.flush() {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                result => result,
+            },
+            Target::Builtin(_) => Ok(()),
+        }
+    }
+}
+
+/// Build the command to execute the external pager.
+///
+/// Returns `None` if the output should not be sent to an external pager.
+fn command(context: &Context) -> Option<Command> {
+    if DISABLED.load(Ordering::Relaxed) || !atty::is(atty::Stream::Stdout) {
+        return None;
+    }
+
+    if is_builtin_requested() {
+        return None;
+    }
+
+    let mut pager_args = pager_args()?.into_iter();
+
     let mut cmd = Command::new(pager_args.next()?);
 
     for arg in pager_args {
@@ -31,7 +198,70 @@ pub fn command(repository: Option<&str>) -> Option<Command> {
 
     cmd.stdin(Stdio::piped());
     cmd.env("HDCQUERY_VERSION", env!("CARGO_PKG_VERSION"));
-    repository.map(|r| cmd.env("HDCQUERY_REPOSITORY", r));
+    cmd.env("HDCQUERY_COMMAND", context.command);
+    context.query.map(|q| cmd.env("HDCQUERY_QUERY", q));
+    context
+        .repository
+        .map(|r| cmd.env("HDCQUERY_REPOSITORY", r));
 
     Some(cmd)
 }
+
+fn is_builtin_requested() -> bool {
+    matches!(configured(), Some(p) if p.trim() == BUILTIN_PAGER)
+}
+
+/// An empty pager (like `HDC_PAGER=`) writes the output directly to stdout.
+fn is_stdout_requested() -> bool {
+    matches!(configured(), Some(p) if p.trim().is_empty())
+}
+
+/// Check if the pager can display ANSI escape sequences.
+///
+/// This is expected if the pager is `less` with the `-R` (or `-r`) option,
+/// either in its arguments or in the `$LESS` variable.
+fn supports_ansi() -> bool {
+    let args = match pager_args() {
+        Some(args) if !args.is_empty() => args,
+        _ => return false,
+    };
+
+    let raw_option = |arg: &str| {
+        arg.eq_ignore_ascii_case("--raw-control-chars")
+            || (arg.starts_with('-') && !arg.starts_with("--") && arg.contains(&['R', 'r'][..]))
+    };
+
+    if args[1..].iter().any(|a| raw_option(a)) {
+        return true;
+    }
+
+    if Path::new(&args[0]).file_stem() == Some(OsStr::new("less")) {
+        if let Ok(less) = env::var("LESS") {
+            return less.split_whitespace().any(raw_option)
+                || (!less.starts_with('-') && less.contains(&['R', 'r'][..]));
+        }
+    }
+
+    false
+}
+
+/// Pager from `$HDC_PAGER`, or from the configuration file.
+pub fn configured() -> Option<String> {
+    env::var(PAGER_ENV)
+        .ok()
+        .or_else(|| CONFIGURED.get().cloned())
+}
+
+/// Arguments to execute the pager, parsed from `$HDC_PAGER`.
+fn pager_args() -> Option<Vec<String>> {
+    let configured = configured();
+    let pager_args = configured.as_deref().unwrap_or(DEFAULT_PAGER);
+
+    match shell_words::split(pager_args) {
+        Ok(words) => Some(words),
+        Err(e) => {
+            eprintln!("Failed to parse $HDC_PAGER: {:?}", e);
+            None
+        }
+    }
+}
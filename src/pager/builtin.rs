@@ -0,0 +1,315 @@
+//! Minimal pager, used when the external pager is not available.
+//!
+//! If the text fits in the terminal, it is printed directly, like `less -F`.
+//!
+//! Keys:
+//!
+//! * `q`, `Esc`: quit.
+//! * `j`, `Down`, `Enter`: scroll one line down.
+//! * `k`, `Up`: scroll one line up.
+//! * `Space`, `f`, `PageDown`: scroll one page down.
+//! * `b`, `PageUp`: scroll one page up.
+//! * `g`, `Home`: go to the beginning.
+//! * `G`, `End`: go to the end.
+//! * `/`: search a text.
+//! * `n`, `N`: go to the next or previous match.
+
+use std::io::{self, Write};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use unicode_width::UnicodeWidthChar;
+
+use crate::langext::StrExt;
+
+const HELP: &str = "q: quit | /: search | n/N: next/previous match";
+
+pub fn run(text: &str) -> anyhow::Result<()> {
+    let mut stdout = io::stdout();
+    let (width, height) = terminal::size()?;
+
+    let mut pager = Pager::new(text, width as usize, height as usize);
+
+    if pager.rows.len() < pager.page_size() {
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    terminal::enable_raw_mode()?;
+    execute!(stdout, EnterAlternateScreen, Hide)?;
+
+    let result = pager.event_loop(&mut stdout);
+
+    execute!(stdout, Show, LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+
+    result
+}
+
+struct Pager<'a> {
+    text: &'a str,
+
+    /// Lines of `text`, split to fit in the terminal width.
+    rows: Vec<String>,
+
+    width: usize,
+
+    height: usize,
+
+    /// Index of the first visible row.
+    top: usize,
+
+    search: Option<String>,
+
+    message: Option<String>,
+}
+
+impl<'a> Pager<'a> {
+    fn new(text: &'a str, width: usize, height: usize) -> Pager<'a> {
+        Pager {
+            text,
+            rows: split_rows(text, width),
+            width,
+            height,
+            top: 0,
+            search: None,
+            message: None,
+        }
+    }
+
+    fn page_size(&self) -> usize {
+        self.height.saturating_sub(1).max(1)
+    }
+
+    fn max_top(&self) -> usize {
+        self.rows.len().saturating_sub(self.page_size())
+    }
+
+    fn scroll(&mut self, delta: isize) {
+        let top = self.top as isize + delta;
+        self.top = top.max(0).min(self.max_top() as isize) as usize;
+    }
+
+    fn event_loop(&mut self, stdout: &mut io::Stdout) -> anyhow::Result<()> {
+        loop {
+            self.draw(stdout)?;
+
+            let key = match event::read()? {
+                Event::Key(key) => key,
+
+                Event::Resize(width, height) => {
+                    self.width = width as usize;
+                    self.rows = split_rows(self.text, self.width);
+                    self.height = height as usize;
+                    self.scroll(0);
+                    continue;
+                }
+
+                Event::Mouse(_) => continue,
+            };
+
+            self.message = None;
+
+            let page = self.page_size() as isize;
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+
+                KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter => self.scroll(1),
+                KeyCode::Char('k') | KeyCode::Up => self.scroll(-1),
+                KeyCode::Char(' ') | KeyCode::Char('f') | KeyCode::PageDown => self.scroll(page),
+                KeyCode::Char('b') | KeyCode::PageUp => self.scroll(-page),
+                KeyCode::Char('g') | KeyCode::Home => self.top = 0,
+                KeyCode::Char('G') | KeyCode::End => self.top = self.max_top(),
+
+                KeyCode::Char('/') => {
+                    if let Some(pattern) = self.read_pattern(stdout)? {
+                        self.search = Some(pattern);
+                        self.find(true, self.top + 1);
+                    }
+                }
+
+                KeyCode::Char('n') => self.find(true, self.top + 1),
+                KeyCode::Char('N') => self.find(false, self.top),
+
+                _ => self.message = Some(HELP.to_string()),
+            }
+        }
+    }
+
+    /// Move to the next row that contains the search pattern.
+    fn find(&mut self, forward: bool, start: usize) {
+        let pattern = match &self.search {
+            Some(p) if !p.is_empty() => p.to_lowercase(),
+            _ => return,
+        };
+
+        let matches = |row: &String| row.strip_ansi().to_lowercase().contains(&pattern);
+
+        let found = if forward {
+            self.rows
+                .iter()
+                .skip(start)
+                .position(matches)
+                .map(|i| i + start)
+        } else {
+            self.rows[..start.min(self.rows.len())]
+                .iter()
+                .rposition(matches)
+        };
+
+        match found {
+            Some(row) => self.top = row.min(self.max_top()),
+            None => self.message = Some("Pattern not found".to_string()),
+        }
+    }
+
+    /// Read the search pattern in the status line.
+    fn read_pattern(&mut self, stdout: &mut io::Stdout) -> anyhow::Result<Option<String>> {
+        let mut pattern = String::new();
+
+        loop {
+            let status_row = self.page_size() as u16;
+            queue!(
+                stdout,
+                MoveTo(0, status_row),
+                Clear(ClearType::CurrentLine),
+                Print('/'),
+                Print(&pattern),
+                Show,
+            )?;
+            stdout.flush()?;
+
+            if let Event::Key(KeyEvent { code, modifiers }) = event::read()? {
+                match code {
+                    KeyCode::Enter => break,
+                    KeyCode::Esc => {
+                        execute!(stdout, Hide)?;
+                        return Ok(None);
+                    }
+                    KeyCode::Backspace => {
+                        pattern.pop();
+                    }
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                        execute!(stdout, Hide)?;
+                        return Ok(None);
+                    }
+                    KeyCode::Char(c) => pattern.push(c),
+                    _ => (),
+                }
+            }
+        }
+
+        execute!(stdout, Hide)?;
+        Ok(Some(pattern))
+    }
+
+    fn draw(&self, stdout: &mut io::Stdout) -> anyhow::Result<()> {
+        let page_size = self.page_size();
+
+        for line in 0..page_size {
+            queue!(
+                stdout,
+                MoveTo(0, line as u16),
+                Clear(ClearType::CurrentLine)
+            )?;
+
+            if let Some(row) = self.rows.get(self.top + line) {
+                queue!(stdout, Print(row), SetAttribute(Attribute::Reset))?;
+            } else {
+                queue!(stdout, Print('~'))?;
+            }
+        }
+
+        let last = (self.top + page_size).min(self.rows.len());
+        let mut status = match &self.message {
+            Some(message) => message.clone(),
+            None => format!(
+                "lines {}-{}/{} ({}%) | {}",
+                self.top + 1,
+                last,
+                self.rows.len(),
+                last * 100 / self.rows.len().max(1),
+                HELP
+            ),
+        };
+
+        if let Some((index, _)) = status.char_indices().nth(self.width.saturating_sub(1)) {
+            status.truncate(index);
+        }
+
+        queue!(
+            stdout,
+            MoveTo(0, page_size as u16),
+            Clear(ClearType::CurrentLine),
+            SetAttribute(Attribute::Reverse),
+            Print(status),
+            SetAttribute(Attribute::Reset),
+        )?;
+
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Split the lines of `text` in rows with a width not greater than `width`.
+///
+/// ANSI escape sequences are kept in the rows.
+fn split_rows(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut rows = Vec::new();
+
+    for line in text.lines() {
+        let mut row = String::new();
+        let mut row_width = 0;
+        let mut chars = line.chars();
+
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                row.push(c);
+                if let Some(c) = chars.next() {
+                    row.push(c);
+                    if c == '[' {
+                        for c in chars.by_ref() {
+                            row.push(c);
+                            if ('\x40'..='\x7e').contains(&c) {
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                continue;
+            }
+
+            let c = if c == '\t' { ' ' } else { c };
+            let char_width = c.width().unwrap_or(0);
+
+            if row_width + char_width > width {
+                rows.push(std::mem::take(&mut row));
+                row_width = 0;
+            }
+
+            row.push(c);
+            row_width += char_width;
+        }
+
+        rows.push(row);
+    }
+
+    rows
+}
+
+#[test]
+fn split_long_lines() {
+    let rows = split_rows("abcdef\n\n\x1b[1mabc\x1b[0mdef", 4);
+    assert_eq!(rows, ["abcd", "ef", "", "\x1b[1mabc\x1b[0md", "ef"]);
+}
@@ -0,0 +1,93 @@
+//! Download pages from a paginated API in a background task.
+//!
+//! The next pages are downloaded while the current one is being processed
+//! (for example, when the program is waiting for user input). The queue is
+//! bounded to `QUEUE_SIZE`, so we will not download a lot of unneeded pages.
+
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc;
+
+use crate::backend::Backend;
+
+const QUEUE_SIZE: usize = 2;
+
+/// Pages received from a background task.
+pub struct Pages<T> {
+    receiver: mpsc::Receiver<anyhow::Result<T>>,
+}
+
+/// Sender to feed a `Pages` instance.
+pub type Sender<T> = mpsc::Sender<anyhow::Result<T>>;
+
+impl<T> Pages<T> {
+    /// Wait for the next page.
+    ///
+    /// Returns `None` when there are no more pages. Dropping the `Pages`
+    /// instance stops the background task.
+    pub async fn next(&mut self) -> Option<anyhow::Result<T>> {
+        self.receiver.recv().await
+    }
+}
+
+/// Create a queue of pages, to be fed by a custom task.
+pub fn channel<T>() -> (Sender<T>, Pages<T>) {
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+    (sender, Pages { receiver })
+}
+
+/// Start a task to download pages, beginning with `url`.
+///
+/// When a page is received, `next` returns the URL of the following page.
+/// If it returns `None`, or if a request fails, the task stops.
+pub fn prefetch<T, F>(
+    backend: Arc<dyn Backend>,
+    headers: &'static [(&'static str, &'static str)],
+    url: String,
+    mut next: F,
+) -> Pages<T>
+where
+    T: DeserializeOwned + Send + 'static,
+    F: FnMut(&T) -> Option<String> + Send + 'static,
+{
+    let (mut sender, pages) = channel();
+
+    tokio::spawn(async move {
+        let mut url = Some(url);
+
+        while let Some(current) = url.take() {
+            let page = match download(&*backend, headers, &current).await {
+                Ok(page) => page,
+                Err(e) => {
+                    let _ = sender.send(Err(e)).await;
+                    return;
+                }
+            };
+
+            url = next(&page);
+
+            if sender.send(Ok(page)).await.is_err() {
+                // Receiver is closed.
+                return;
+            }
+        }
+    });
+
+    pages
+}
+
+async fn download<T: DeserializeOwned>(
+    backend: &dyn Backend,
+    headers: &[(&str, &str)],
+    url: &str,
+) -> anyhow::Result<T> {
+    let mut all_headers = vec![("Accept", "application/json")];
+    all_headers.extend_from_slice(headers);
+
+    backend
+        .get(url, &all_headers)
+        .await?
+        .error_for_status()?
+        .json()
+}
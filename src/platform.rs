@@ -0,0 +1,229 @@
+//! Platforms of the images, like `linux/arm/v7`.
+//!
+//! The platform of the current machine is detected at runtime, so an
+//! emulated binary (like an x86 program on an ARM machine) gets the platform
+//! of the host.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Platform {
+    pub os: String,
+    pub architecture: String,
+    pub variant: Option<String>,
+}
+
+impl Platform {
+    /// Platform of the machine where the program is running.
+    pub fn current() -> Option<Platform> {
+        let os = match std::env::consts::OS {
+            "macos" => "darwin",
+            os => os,
+        };
+
+        let (architecture, variant) = current_machine()?;
+
+        Some(Platform {
+            os: os.to_string(),
+            architecture,
+            variant,
+        })
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(os), Some(arch), variant, None) if !os.is_empty() && !arch.is_empty() => {
+                Ok(Platform {
+                    os: os.to_string(),
+                    architecture: arch.to_string(),
+                    variant: variant.filter(|v| !v.is_empty()).map(str::to_string),
+                })
+            }
+
+            _ => Err(format!(
+                "invalid platform {:?} (expected os/arch[/variant])",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Variant assumed when an image does not specify it.
+pub fn default_variant(architecture: &str) -> Option<&'static str> {
+    match architecture {
+        "arm64" => Some("v8"),
+        "arm" => Some("v7"),
+        _ => None,
+    }
+}
+
+/// Convert a machine name (from `uname -m`) to an architecture and a variant,
+/// using the names from the Go runtime.
+fn from_machine(machine: &str) -> Option<(&'static str, Option<&'static str>)> {
+    let platform = match machine {
+        "x86_64" | "amd64" | "AMD64" => ("amd64", None),
+        "i386" | "i486" | "i586" | "i686" | "x86" => ("386", None),
+        "aarch64" | "arm64" | "armv8" | "ARM64" => ("arm64", Some("v8")),
+        "armv8l" | "armv7l" | "armv7" => ("arm", Some("v7")),
+        "armv6l" | "armv6" => ("arm", Some("v6")),
+        "armv5tel" | "armv5l" | "armv5" => ("arm", Some("v5")),
+        "ppc64le" => ("ppc64le", None),
+        "s390x" => ("s390x", None),
+        "mips64" => ("mips64", None),
+        "mips64el" => ("mips64le", None),
+        "riscv64" => ("riscv64", None),
+        _ => return None,
+    };
+
+    Some(platform)
+}
+
+#[cfg(unix)]
+fn current_machine() -> Option<(String, Option<String>)> {
+    let machine = uname_machine()?;
+
+    // A binary for x86_64 running under Rosetta gets the machine of the
+    // emulated CPU.
+    let machine = if cfg!(target_os = "macos") && is_translated() {
+        "arm64".to_string()
+    } else {
+        machine
+    };
+
+    let (arch, mut variant) = from_machine(&machine)?;
+
+    // 32-bit ARM kernels can run on newer CPUs. The actual version is
+    // available in /proc/cpuinfo.
+    if arch == "arm" {
+        if let Some(v) = cpuinfo_arm_version() {
+            variant = match v {
+                5 => Some("v5"),
+                6 => Some("v6"),
+                _ => Some("v7"),
+            };
+        }
+    }
+
+    Some((arch.to_string(), variant.map(str::to_string)))
+}
+
+#[cfg(unix)]
+fn uname_machine() -> Option<String> {
+    // SAFETY: `utsname` only contains arrays of `c_char`, so a zeroed value
+    // is valid. If `uname` succeeds, `machine` is a NUL-terminated string,
+    // and it is copied before `uts` is dropped.
+    unsafe {
+        let mut uts: libc::utsname = std::mem::zeroed();
+
+        if libc::uname(&mut uts) != 0 {
+            return None;
+        }
+
+        let machine = std::ffi::CStr::from_ptr(uts.machine.as_ptr());
+        Some(machine.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn is_translated() -> bool {
+    std::process::Command::new("sysctl")
+        .args(["-n", "sysctl.proc_translated"])
+        .output()
+        .map(|output| output.stdout.starts_with(b"1"))
+        .unwrap_or(false)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn is_translated() -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn cpuinfo_arm_version() -> Option<u32> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    parse_cpu_architecture(&cpuinfo)
+}
+
+/// Extract the `CPU architecture` field from `/proc/cpuinfo`.
+#[cfg(unix)]
+fn parse_cpu_architecture(cpuinfo: &str) -> Option<u32> {
+    cpuinfo.lines().find_map(|line| {
+        let mut parts = line.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if key.trim() == "CPU architecture" => {
+                let value = value.trim();
+                let digits = value
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(value.len());
+                value[..digits].parse().ok()
+            }
+
+            _ => None,
+        }
+    })
+}
+
+#[cfg(windows)]
+fn current_machine() -> Option<(String, Option<String>)> {
+    // PROCESSOR_ARCHITEW6432 is defined only for 32-bit programs running in
+    // a 64-bit system.
+    let machine = std::env::var("PROCESSOR_ARCHITEW6432")
+        .or_else(|_| std::env::var("PROCESSOR_ARCHITECTURE"))
+        .ok()?;
+
+    let (arch, variant) = from_machine(&machine)?;
+    Some((arch.to_string(), variant.map(str::to_string)))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn current_machine() -> Option<(String, Option<String>)> {
+    None
+}
+
+#[test]
+fn parse_platforms() {
+    let platform: Platform = "linux/arm/v7".parse().unwrap();
+    assert_eq!(platform.os, "linux");
+    assert_eq!(platform.architecture, "arm");
+    assert_eq!(platform.variant.as_deref(), Some("v7"));
+    assert_eq!(platform.to_string(), "linux/arm/v7");
+
+    let platform: Platform = "windows/amd64".parse().unwrap();
+    assert_eq!(platform.variant, None);
+    assert_eq!(platform.to_string(), "windows/amd64");
+
+    assert!("linux".parse::<Platform>().is_err());
+    assert!("linux/".parse::<Platform>().is_err());
+    assert!("linux/arm/v7/x".parse::<Platform>().is_err());
+
+    assert_eq!(from_machine("armv6l"), Some(("arm", Some("v6"))));
+    assert_eq!(from_machine("aarch64"), Some(("arm64", Some("v8"))));
+}
+
+#[cfg(unix)]
+#[test]
+fn parse_cpuinfo() {
+    let cpuinfo =
+        "processor\t: 0\nmodel name\t: ARMv7 Processor rev 4 (v7l)\nCPU architecture: 7\n";
+    assert_eq!(parse_cpu_architecture(cpuinfo), Some(7));
+    assert_eq!(parse_cpu_architecture("CPU architecture: 5TEJ"), Some(5));
+}
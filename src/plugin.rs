@@ -0,0 +1,199 @@
+//! External commands, like git subcommands.
+//!
+//! If the command is not a built-in command (or an alias), hdcquery looks
+//! for an executable named `hdcquery-{command}` in `$PATH`, and executes it
+//! with the rest of the arguments.
+//!
+//! The plugin receives the following environment variables:
+//!
+//! * `HDCQUERY_CREDENTIALS`: source of the credentials, `none` or `docker`.
+//! * `HDCQUERY_EXE`: path of the hdcquery executable.
+//! * `HDCQUERY_HUB_URL`: URL for Docker Hub, from `--hub-url` or from the
+//!   configuration file.
+//! * `HDCQUERY_NO_PAGER`: `1` if `--no-pager` is given.
+//! * `HDCQUERY_OUTPUT`: output format, `text` or `json`.
+//! * `HDCQUERY_TRACE_HTTP`: level of `-v`, if it is given.
+//! * `HDCQUERY_VERSION`: version of hdcquery.
+//! * `HDC_PAGER`: pager from the configuration file, if `$HDC_PAGER` is not
+//!   set.
+//!
+//! The global options given before the command (like `--hub-url`) are used
+//! only to compute these variables.
+//!
+//! Secrets are never passed to plugins, since any `hdcquery-*` executable in
+//! `$PATH` is a plugin. A plugin that needs credentials can read them from
+//! the source in `HDCQUERY_CREDENTIALS`, or execute `HDCQUERY_EXE`.
+
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Context;
+
+use crate::credentials;
+use crate::options::{self, Options, OutputFormat};
+
+const PREFIX: &str = "hdcquery-";
+
+pub struct Plugin {
+    /// Position of the command in the arguments.
+    pub index: usize,
+
+    path: PathBuf,
+}
+
+impl Plugin {
+    /// Find the plugin for the command in `args`, if it is not a built-in
+    /// command.
+    pub fn find(args: &[String]) -> Option<Plugin> {
+        let index = options::command_index(args)?;
+        let name = args[index].as_str();
+
+        if options::builtin_commands().contains(&name) {
+            return None;
+        }
+
+        let path = find_in_path(name, &env::var_os("PATH")?)?;
+        Some(Plugin { index, path })
+    }
+
+    /// Execute the plugin, and return its exit code.
+    pub fn run(&self, args: &[String], options: &Options) -> anyhow::Result<i32> {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(&args[self.index + 1..]);
+
+        cmd.env("HDCQUERY_VERSION", env!("CARGO_PKG_VERSION"));
+
+        cmd.env(
+            "HDCQUERY_HUB_URL",
+            options
+                .hub_url
+                .as_deref()
+                .unwrap_or(crate::backend::DOCKER_HUB_URL),
+        );
+
+        if let Ok(exe) = env::current_exe() {
+            cmd.env("HDCQUERY_EXE", exe);
+        }
+
+        if options.no_pager {
+            cmd.env("HDCQUERY_NO_PAGER", "1");
+        }
+
+        let output = match options.output.unwrap_or_default() {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+        };
+
+        cmd.env("HDCQUERY_OUTPUT", output);
+
+        let credentials = match options.credentials.unwrap_or(credentials::Source::None) {
+            credentials::Source::None => "none",
+            credentials::Source::Docker => "docker",
+        };
+
+        cmd.env("HDCQUERY_CREDENTIALS", credentials);
+
+        if options.trace_http > 0 {
+            cmd.env("HDCQUERY_TRACE_HTTP", options.trace_http.to_string());
+        }
+
+        if let Some(pager) = crate::pager::configured() {
+            cmd.env(crate::pager::PAGER_ENV, pager);
+        }
+
+        let status = cmd
+            .status()
+            .with_context(|| format!("can't execute {}", self.path.display()))?;
+
+        // Without an exit code, the plugin was killed by a signal.
+        Ok(status.code().unwrap_or(1))
+    }
+}
+
+/// Find the executable for the plugin `name` in the directories of `paths`.
+fn find_in_path(name: &str, paths: &OsStr) -> Option<PathBuf> {
+    // The name can't be used to escape from the directories.
+    if name.is_empty() || name.contains(&['/', '\\'][..]) || name == ".." {
+        return None;
+    }
+
+    let file_name = format!("{}{}{}", PREFIX, name, env::consts::EXE_SUFFIX);
+
+    env::split_paths(paths)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match path.metadata() {
+        Ok(meta) => meta.is_file() && meta.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(unix)]
+#[test]
+fn run_plugins_from_path() {
+    use assert_cmd::prelude::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::temp_dir().join(format!("hdcquery-plugins-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let script = dir.join("hdcquery-hello");
+    std::fs::write(
+        &script,
+        concat!(
+            "#!/bin/sh\n",
+            "echo \"$HDCQUERY_HUB_URL $HDCQUERY_NO_PAGER $HDCQUERY_OUTPUT $*\"\n",
+            // Only the source of the credentials is passed, not the secrets.
+            "echo \"$HDCQUERY_CREDENTIALS\"\n",
+            "exit 3\n",
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert_eq!(find_in_path("hello", dir.as_os_str()), Some(script));
+    assert_eq!(find_in_path("../hello", dir.as_os_str()), None);
+
+    let mut path = env::split_paths(&env::var_os("PATH").unwrap()).collect::<Vec<_>>();
+    path.insert(0, dir.clone());
+
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env("PATH", env::join_paths(path).unwrap())
+        .env("XDG_CONFIG_HOME", &dir)
+        .args([
+            "--no-pager",
+            "--hub-url",
+            "http://mock",
+            "--output",
+            "json",
+            "--credentials",
+            "docker",
+            "hello",
+            "-x",
+            "world",
+        ])
+        .output()
+        .unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        "http://mock 1 json -x world\ndocker\n"
+    );
+    assert_eq!(output.status.code(), Some(3));
+}
@@ -0,0 +1,155 @@
+//! Line editor for the interactive prompt of the 'search' command.
+//!
+//! Lines are stored in a persistent history, in
+//! `$XDG_STATE_HOME/hdcquery/history`.
+//!
+//! The completion offers the numbers of the results printed so far and the
+//! commands in `COMMANDS`.
+
+use std::path::PathBuf;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Config, Context, Editor};
+
+/// Commands accepted by the prompt, in addition to the result numbers.
+pub const COMMANDS: &[&str] = &["next", "quit"];
+
+const HISTORY_FILE: &str = "history";
+
+const HISTORY_SIZE: usize = 1000;
+
+pub struct Prompt {
+    editor: Editor<Helper>,
+    history_path: Option<PathBuf>,
+}
+
+impl Prompt {
+    pub fn new() -> Prompt {
+        let config = Config::builder()
+            .max_history_size(HISTORY_SIZE)
+            .history_ignore_dups(true)
+            .history_ignore_space(true)
+            .completion_type(CompletionType::List)
+            .build();
+
+        let mut editor = Editor::with_config(config);
+        editor.set_helper(Some(Helper { results: 0 }));
+
+        let history_path = crate::xdg::state_home().map(|dir| dir.join(HISTORY_FILE));
+        if let Some(path) = &history_path {
+            // The file does not exist on the first execution.
+            let _ = editor.load_history(path);
+        }
+
+        Prompt {
+            editor,
+            history_path,
+        }
+    }
+
+    /// Update the number of results available for completion.
+    pub fn set_results(&mut self, results: usize) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.results = results;
+        }
+    }
+
+    /// Read a line from the terminal.
+    ///
+    /// Returns `None` if the user sends EOF (`^D`) or interrupts the
+    /// prompt (`^C`).
+    pub fn read_line(&mut self, prompt: &str) -> anyhow::Result<Option<String>> {
+        match self.editor.readline(prompt) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    self.editor.add_history_entry(line.as_str());
+                }
+
+                Ok(Some(line))
+            }
+
+            Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => Ok(None),
+
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for Prompt {
+    fn drop(&mut self) {
+        let path = match &self.history_path {
+            Some(p) => p,
+            None => return,
+        };
+
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        if let Err(e) = self.editor.save_history(path) {
+            eprintln!("Failed to save history to {}: {}", path.display(), e);
+        }
+    }
+}
+
+struct Helper {
+    results: usize,
+}
+
+impl Completer for Helper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = line[..pos].trim_start();
+        let start = pos - prefix.len();
+
+        let candidates = if !prefix.is_empty() && prefix.chars().all(|c| c.is_ascii_digit()) {
+            (1..=self.results)
+                .map(|n| n.to_string())
+                .filter(|n| n.starts_with(prefix))
+                .collect()
+        } else {
+            COMMANDS
+                .iter()
+                .filter(|c| c.starts_with(prefix))
+                .map(|c| c.to_string())
+                .collect()
+        };
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Helper {
+    type Hint = String;
+}
+
+impl Highlighter for Helper {}
+
+impl Validator for Helper {}
+
+impl rustyline::Helper for Helper {}
+
+#[test]
+fn complete_numbers_and_commands() {
+    let helper = Helper { results: 12 };
+    let history = rustyline::history::History::new();
+    let ctx = Context::new(&history);
+
+    let complete = |line: &str| helper.complete(line, line.len(), &ctx).unwrap();
+
+    assert_eq!(complete("1").1, ["1", "10", "11", "12"]);
+    assert_eq!(complete("  q"), (2, vec!["quit".to_string()]));
+    assert_eq!(complete("ne").1, ["next"]);
+    assert_eq!(complete("").1, COMMANDS);
+    assert!(complete("x").1.is_empty());
+}
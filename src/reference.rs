@@ -0,0 +1,246 @@
+//! Parser for image references, like `docker.io/library/redis:7`.
+//!
+//! The grammar is the same used by the Docker distribution project:
+//!
+//! ```text
+//! reference := name [ ":" tag ] [ "@" digest ]
+//! name      := [ domain "/" ] path-component [ "/" path-component ]*
+//! ```
+//!
+//! The first component is a domain only if it contains a `.` or a `:`, or
+//! if it is `localhost`. References without a domain are in Docker Hub, and
+//! single-component names in Docker Hub are in the `library` namespace.
+
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+
+use futures::stream::{self, Stream, StreamExt};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Registry used when the reference does not contain a domain.
+pub const DOCKER_HUB: &str = "docker.io";
+
+/// Alternative names for Docker Hub.
+const DOCKER_HUB_ALIASES: &[&str] = &[
+    "docker.io",
+    "index.docker.io",
+    "registry-1.docker.io",
+    "registry.hub.docker.com",
+    "hub.docker.com",
+];
+
+const MAX_NAME_LENGTH: usize = 255;
+
+static DOMAIN: Lazy<Regex> = Lazy::new(|| {
+    regex(
+        r"^(?:[a-zA-Z0-9](?:[a-zA-Z0-9-]*[a-zA-Z0-9])?(?:\.[a-zA-Z0-9](?:[a-zA-Z0-9-]*[a-zA-Z0-9])?)*|\[[0-9a-fA-F:]+\])(?::[0-9]+)?$",
+    )
+});
+
+static PATH_COMPONENT: Lazy<Regex> =
+    Lazy::new(|| regex(r"^[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*$"));
+
+static TAG: Lazy<Regex> = Lazy::new(|| regex(r"^[A-Za-z0-9_][A-Za-z0-9_.-]{0,127}$"));
+
+static DIGEST: Lazy<Regex> =
+    Lazy::new(|| regex(r"^[A-Za-z][A-Za-z0-9]*(?:[-_+.][A-Za-z][A-Za-z0-9]*)*:[0-9a-fA-F]{32,}$"));
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    /// Domain of the registry, like `docker.io` or `ghcr.io`.
+    pub registry: String,
+
+    /// Path of the repository in the registry, like `library/redis`.
+    pub path: String,
+
+    pub tag: Option<String>,
+
+    pub digest: Option<String>,
+}
+
+impl Reference {
+    /// Check if the repository is in Docker Hub.
+    pub fn is_docker_hub(&self) -> bool {
+        self.registry == DOCKER_HUB
+    }
+
+    /// Name of the repository, including the registry if it is not Docker
+    /// Hub, like `library/redis` or `ghcr.io/foo/bar`.
+    pub fn full_name(&self) -> String {
+        if self.is_docker_hub() {
+            self.path.clone()
+        } else {
+            format!("{}/{}", self.registry, self.path)
+        }
+    }
+}
+
+impl FromStr for Reference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| Err(format!("invalid reference {:?}: {}", s, reason));
+
+        let (rest, digest) = match s.find('@') {
+            Some(at) => (&s[..at], Some(&s[at + 1..])),
+            None => (s, None),
+        };
+
+        // The tag is after the last ':', only if it is not part of the
+        // domain (like in `localhost:5000/foo`).
+        let (name, tag) = match rest.rfind(':') {
+            Some(colon) if !rest[colon..].contains('/') => {
+                (&rest[..colon], Some(&rest[colon + 1..]))
+            }
+            _ => (rest, None),
+        };
+
+        if name.is_empty() {
+            return invalid("missing repository name");
+        }
+
+        if name.len() > MAX_NAME_LENGTH {
+            return invalid("repository name is too long");
+        }
+
+        let (domain, path) = match name.find('/') {
+            Some(slash)
+                if name[..slash].contains(&['.', ':'][..]) || &name[..slash] == "localhost" =>
+            {
+                (&name[..slash], &name[slash + 1..])
+            }
+
+            _ => (DOCKER_HUB, name),
+        };
+
+        if !DOMAIN.is_match(domain) {
+            return invalid("invalid registry domain");
+        }
+
+        if !path.split('/').all(|c| PATH_COMPONENT.is_match(c)) {
+            return invalid("repository name must be lowercase, with components separated by '/'");
+        }
+
+        if let Some(tag) = tag {
+            if !TAG.is_match(tag) {
+                return invalid("invalid tag");
+            }
+        }
+
+        if let Some(digest) = digest {
+            if !DIGEST.is_match(digest) {
+                return invalid("invalid digest");
+            }
+        }
+
+        let registry = if DOCKER_HUB_ALIASES.contains(&domain) {
+            DOCKER_HUB
+        } else {
+            domain
+        };
+
+        let path = if registry == DOCKER_HUB && !path.contains('/') {
+            format!("library/{}", path)
+        } else {
+            path.to_string()
+        };
+
+        Ok(Reference {
+            registry: registry.to_string(),
+            path,
+            tag: tag.map(str::to_string),
+            digest: digest.map(str::to_string),
+        })
+    }
+}
+
+/// The reference is written in its shortest form, like `redis:7`.
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_docker_hub() {
+            let path = self.path.strip_prefix("library/").unwrap_or(&self.path);
+            f.write_str(path)?;
+        } else {
+            write!(f, "{}/{}", self.registry, self.path)?;
+        }
+
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Download the data of every repository with `fetch`, up to `jobs` at the
+/// same time.
+///
+/// Repositories are downloaded concurrently, but the results are returned in
+/// the same order as in `references` (usually, as in the command line).
+pub fn fetch_all<'a, F, T>(
+    references: &'a [Reference],
+    jobs: usize,
+    fetch: F,
+) -> impl Stream<Item = T::Output> + Unpin + 'a
+where
+    F: FnMut(&'a Reference) -> T + Unpin + 'a,
+    T: Future + 'a,
+{
+    stream::iter(references).map(fetch).buffered(jobs.max(1))
+}
+
+fn regex(pattern: &str) -> Regex {
+    Regex::new(pattern).expect("valid regex")
+}
+
+#[test]
+fn parse_references() {
+    let parse = |s: &str| s.parse::<Reference>();
+
+    let r = parse("redis").unwrap();
+    assert_eq!(
+        (r.registry.as_str(), r.path.as_str()),
+        ("docker.io", "library/redis")
+    );
+    assert_eq!((r.tag, r.digest), (None, None));
+
+    let r = parse("redis:7").unwrap();
+    assert_eq!(r.path, "library/redis");
+    assert_eq!(r.tag.as_deref(), Some("7"));
+    assert_eq!(r.to_string(), "redis:7");
+
+    let digest = "sha256:466da50d1e0ba009816a4b507a9b526a34169e026e967f304679b1553cbca66c";
+    let r = parse(&format!("docker.io/library/redis:7@{}", digest)).unwrap();
+    assert_eq!(r.path, "library/redis");
+    assert_eq!(r.tag.as_deref(), Some("7"));
+    assert_eq!(r.digest.as_deref(), Some(digest));
+
+    let r = parse("registry-1.docker.io/foo/bar").unwrap();
+    assert_eq!(
+        (r.registry.as_str(), r.path.as_str()),
+        ("docker.io", "foo/bar")
+    );
+    assert_eq!(r.full_name(), "foo/bar");
+
+    let r = parse("localhost:5000/foo/bar:1.0").unwrap();
+    assert_eq!(r.registry, "localhost:5000");
+    assert_eq!(r.path, "foo/bar");
+    assert_eq!(r.tag.as_deref(), Some("1.0"));
+    assert_eq!(r.full_name(), "localhost:5000/foo/bar");
+
+    let r = parse("ghcr.io/owner/image").unwrap();
+    assert_eq!(r.to_string(), "ghcr.io/owner/image");
+
+    assert!(parse("").is_err());
+    assert!(parse("Redis").is_err());
+    assert!(parse("redis:").is_err());
+    assert!(parse("foo//bar").is_err());
+    assert!(parse("redis@sha256:1234").is_err());
+    assert!(parse("-redis").is_err());
+}
@@ -0,0 +1,463 @@
+//! Client for registries implementing the OCI distribution API.
+//!
+//! It is used for repositories that are not in Docker Hub, like `ghcr.io` or
+//! `quay.io`. Bearer tokens are requested when the registry responds with a
+//! `WWW-Authenticate` challenge, anonymously or with the credentials from
+//! `--credentials`.
+//!
+//! The data is converted to the types used by Docker Hub, so the commands
+//! can show it in the same way.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use reqwest::Url;
+
+use crate::backend::{Backend, Response};
+use crate::hubapi::{Image, Repository, Tag};
+use crate::reference::Reference;
+
+/// Media types accepted when a manifest is requested.
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+/// Number of manifests to download concurrently for a tag.
+const MANIFEST_JOBS: usize = 4;
+
+/// Annotation (or label) with the description of an image.
+const DESCRIPTION_ANNOTATION: &str = "org.opencontainers.image.description";
+
+/// Manifest or index of manifests.
+#[derive(serde::Deserialize, Debug, Default)]
+struct Manifest {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Descriptor {
+    digest: String,
+    size: u64,
+    platform: Option<Platform>,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+struct Platform {
+    architecture: String,
+    os: String,
+    variant: Option<String>,
+    #[serde(rename = "os.version")]
+    os_version: Option<String>,
+}
+
+/// Image configuration.
+#[derive(serde::Deserialize, Debug, Default)]
+struct Config {
+    #[serde(flatten)]
+    platform: Platform,
+    created: Option<DateTime<Utc>>,
+    config: Option<ContainerConfig>,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+struct ContainerConfig {
+    #[serde(rename = "Labels")]
+    labels: Option<HashMap<String, String>>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct TagList {
+    tags: Option<Vec<String>>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+pub struct Registry<'a> {
+    backend: &'a dyn Backend,
+    base_url: Url,
+    reference: &'a Reference,
+    token: Mutex<Option<String>>,
+}
+
+impl<'a> Registry<'a> {
+    pub fn new(backend: &'a dyn Backend, reference: &'a Reference) -> anyhow::Result<Self> {
+        // Like Docker, plain HTTP is used only for local registries.
+        let scheme = if reference.registry.starts_with("localhost")
+            || reference.registry.starts_with("127.")
+        {
+            "http"
+        } else {
+            "https"
+        };
+
+        let base_url = format!("{}://{}/v2/{}/", scheme, reference.registry, reference.path);
+
+        Ok(Registry {
+            backend,
+            base_url: Url::parse(&base_url)?,
+            reference,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Names of all tags in the repository, in the order returned by the
+    /// registry.
+    pub async fn tag_names(&self) -> anyhow::Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut url = Some(self.base_url.join("tags/list")?);
+
+        while let Some(current) = url.take() {
+            let response = self.get(current.clone(), "application/json").await?;
+
+            // The next page is in the `Link` header, like
+            // `</v2/foo/tags/list?last=bar&n=100>; rel="next"`.
+            if let Some(next) = response.header("Link").and_then(next_link) {
+                url = Some(current.join(next)?);
+            }
+
+            let list: TagList = response.json()?;
+            names.extend(list.tags.unwrap_or_default());
+        }
+
+        Ok(names)
+    }
+
+    /// Download the manifests of a tag, and build a `Tag` with its images.
+    ///
+    /// The push time is not available in the registry, so `last_updated` is
+    /// the creation time of the image.
+    pub async fn tag(&self, name: &str) -> anyhow::Result<Tag> {
+        let (manifest, digest) = self.manifest(name).await?;
+
+        let images = if manifest.manifests.is_empty() {
+            vec![self.image(manifest, digest, None).await?]
+        } else {
+            stream::iter(manifest.manifests)
+                .map(|descriptor| async move {
+                    let (manifest, _) = self.manifest(&descriptor.digest).await?;
+                    self.image(manifest, Some(descriptor.digest), descriptor.platform)
+                        .await
+                })
+                .buffered(MANIFEST_JOBS)
+                .try_collect()
+                .await?
+        };
+
+        let last_updated = images
+            .iter()
+            .find(|image| !image.is_attestation())
+            .and_then(|image| image.created);
+
+        Ok(Tag {
+            name: name.to_string(),
+            images: images.into_iter().map(|image| image.image).collect(),
+            last_updated,
+            attestations: false,
+        })
+    }
+
+    /// Build a `Repository` with the data available in the registry.
+    ///
+    /// The description is taken from the annotations of the manifest, or from
+    /// the labels of the image.
+    pub async fn repository(&self) -> anyhow::Result<Repository> {
+        let tag = self
+            .reference
+            .digest
+            .as_deref()
+            .or(self.reference.tag.as_deref())
+            .unwrap_or("latest");
+
+        let (mut manifest, _) = self.manifest(tag).await?;
+        let mut description = manifest.annotations.remove(DESCRIPTION_ANNOTATION);
+
+        // Use the first image of an index.
+        if let Some(descriptor) = manifest.manifests.first() {
+            let (child, _) = self.manifest(&descriptor.digest).await?;
+            manifest = child;
+        }
+
+        if description.is_none() {
+            description = manifest.annotations.remove(DESCRIPTION_ANNOTATION);
+        }
+
+        let config = match &manifest.config {
+            Some(config) => self.config(&config.digest).await?,
+            None => Config::default(),
+        };
+
+        if description.is_none() {
+            description = config
+                .config
+                .and_then(|c| c.labels)
+                .and_then(|mut labels| labels.remove(DESCRIPTION_ANNOTATION));
+        }
+
+        let (namespace, name) = match self.reference.path.rfind('/') {
+            Some(slash) => (
+                format!(
+                    "{}/{}",
+                    self.reference.registry,
+                    &self.reference.path[..slash]
+                ),
+                &self.reference.path[slash + 1..],
+            ),
+            None => (self.reference.registry.clone(), &self.reference.path[..]),
+        };
+
+        Ok(Repository {
+            namespace: Some(namespace),
+            name: Some(name.to_string()),
+            description,
+            star_count: None,
+            pull_count: None,
+            full_description: None,
+            is_automated: None,
+            last_updated: config.created,
+        })
+    }
+
+    /// Build an image from its manifest.
+    ///
+    /// If the platform is not in the index, it is read from the image
+    /// configuration.
+    async fn image(
+        &self,
+        manifest: Manifest,
+        digest: Option<String>,
+        platform: Option<Platform>,
+    ) -> anyhow::Result<CreatedImage> {
+        let is_attestation = matches!(&platform, Some(p) if p.os == "unknown");
+
+        // Attestations don't have a configuration with a platform.
+        let (platform, created) = match (&manifest.config, platform) {
+            (Some(config), platform) if !is_attestation => {
+                let config = self.config(&config.digest).await?;
+                (platform.unwrap_or(config.platform), config.created)
+            }
+
+            (_, platform) => (platform.unwrap_or_default(), None),
+        };
+
+        let image = Image {
+            architecture: platform.architecture,
+            variant: platform.variant,
+            os: platform.os,
+            os_version: platform.os_version,
+            size: manifest.layers.iter().map(|l| l.size).sum(),
+            digest,
+        };
+
+        Ok(CreatedImage { image, created })
+    }
+
+    /// Download a manifest. Returns the manifest and its digest.
+    async fn manifest(&self, reference: &str) -> anyhow::Result<(Manifest, Option<String>)> {
+        let url = self.base_url.join(&format!("manifests/{}", reference))?;
+        let response = self.get(url, MANIFEST_TYPES).await?;
+
+        let digest = response.header("Docker-Content-Digest").map(str::to_string);
+
+        Ok((response.json()?, digest))
+    }
+
+    async fn config(&self, digest: &str) -> anyhow::Result<Config> {
+        let url = self.base_url.join(&format!("blobs/{}", digest))?;
+        self.get(url, "*/*").await?.json()
+    }
+
+    /// Send a GET request.
+    ///
+    /// If the registry requires a token, it is requested and the request is
+    /// sent again.
+    async fn get(&self, url: Url, accept: &str) -> anyhow::Result<Response> {
+        let mut response = self.send(&url, accept).await?;
+
+        if response.status == 401 {
+            if let Some(challenge) = response
+                .header("WWW-Authenticate")
+                .and_then(parse_challenge)
+            {
+                let token = self.request_token(challenge).await?;
+                *self.token.lock().unwrap() = Some(token);
+                response = self.send(&url, accept).await?;
+            }
+        }
+
+        response.error_for_status()
+    }
+
+    async fn send(&self, url: &Url, accept: &str) -> anyhow::Result<Response> {
+        let authorization = self
+            .token
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|token| format!("Bearer {}", token));
+
+        let mut headers = vec![("Accept", accept)];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+
+        self.backend.get(url.as_str(), &headers).await
+    }
+
+    /// Request a token to the authentication server.
+    async fn request_token(&self, challenge: Challenge) -> anyhow::Result<String> {
+        let mut url = Url::parse(&challenge.realm)?;
+
+        {
+            let mut query = url.query_pairs_mut();
+
+            if let Some(service) = &challenge.service {
+                query.append_pair("service", service);
+            }
+
+            match &challenge.scope {
+                Some(scope) => query.append_pair("scope", scope),
+                None => {
+                    query.append_pair("scope", &format!("repository:{}:pull", self.reference.path))
+                }
+            };
+        }
+
+        let authorization = crate::credentials::authorization(&self.reference.registry)?;
+
+        let mut headers = vec![("Accept", "application/json")];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+
+        let response: TokenResponse = self
+            .backend
+            .get(url.as_str(), &headers)
+            .await?
+            .error_for_status()?
+            .json()?;
+
+        match response.token.or(response.access_token) {
+            Some(token) => Ok(token),
+            None => anyhow::bail!(
+                "{}: no token in the authentication response",
+                self.reference
+            ),
+        }
+    }
+}
+
+/// Image with its creation time.
+struct CreatedImage {
+    image: Image,
+    created: Option<DateTime<Utc>>,
+}
+
+impl CreatedImage {
+    fn is_attestation(&self) -> bool {
+        self.image.is_attestation()
+    }
+}
+
+/// Parameters of a `Bearer` challenge in the `WWW-Authenticate` header.
+#[derive(Debug, PartialEq)]
+struct Challenge {
+    realm: String,
+    service: Option<String>,
+    scope: Option<String>,
+}
+
+/// Parse a header like `Bearer realm="…",service="…",scope="…"`.
+fn parse_challenge(header: &str) -> Option<Challenge> {
+    let header = header.trim();
+
+    if header.len() < 7 || !header[..7].eq_ignore_ascii_case("bearer ") {
+        return None;
+    }
+
+    let mut params = HashMap::new();
+    let mut rest = header[7..].trim_start();
+
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim().to_ascii_lowercase();
+        rest = &rest[eq + 1..];
+
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            rest = &quoted[end + 1..];
+            &quoted[..end]
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value.trim()
+        };
+
+        params.insert(key, value.to_string());
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+
+    Some(Challenge {
+        realm: params.remove("realm")?,
+        service: params.remove("service"),
+        scope: params.remove("scope"),
+    })
+}
+
+/// Extract the URL with `rel="next"` from a `Link` header.
+fn next_link(header: &str) -> Option<&str> {
+    header.split(',').find_map(|link| {
+        let mut parts = link.split(';');
+        let url = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+
+        if parts.any(|p| p.trim().replace(' ', "") == "rel=\"next\"") {
+            Some(url)
+        } else {
+            None
+        }
+    })
+}
+
+#[test]
+fn parse_auth_headers() {
+    assert_eq!(
+        parse_challenge(
+            r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:foo/bar:pull""#
+        ),
+        Some(Challenge {
+            realm: "https://ghcr.io/token".to_string(),
+            service: Some("ghcr.io".to_string()),
+            scope: Some("repository:foo/bar:pull".to_string()),
+        })
+    );
+
+    assert_eq!(
+        parse_challenge(r#"bearer realm="https://quay.io/v2/auth", service=quay.io"#),
+        Some(Challenge {
+            realm: "https://quay.io/v2/auth".to_string(),
+            service: Some("quay.io".to_string()),
+            scope: None,
+        })
+    );
+
+    assert_eq!(parse_challenge(r#"Basic realm="Registry""#), None);
+
+    assert_eq!(
+        next_link(r#"</v2/foo/tags/list?last=b&n=2>; rel="next""#),
+        Some("/v2/foo/tags/list?last=b&n=2")
+    );
+    assert_eq!(next_link(r#"</v2/foo/tags/list?n=2>; rel="prev""#), None);
+}
@@ -2,17 +2,22 @@
 
 use std::io::Write;
 use std::str::FromStr;
+use std::sync::Arc;
 
+use crate::backend::Backend;
 use crate::hubapi::Summary;
 use crate::langext::DurationExt;
-use crate::options::SearchOptions;
-
-use tokio::io::AsyncBufReadExt;
-use tokio::sync::mpsc;
+use crate::options::{OutputFormat, SearchOptions, SearchSort};
+use crate::pages::{self, Pages};
+use crate::table::{terminal_width, Column, Table};
 
 const DEFAULT_SEARCH_URL: &str = "https://hub.docker.com/api/content/v1/products/search";
 
-const PAGES_QUEUE_SIZE: usize = 2;
+/// Maximum number of results to sort if `--limit` is not given.
+const DEFAULT_SORT_LIMIT: usize = 100;
+
+/// Page size to download results that have to be sorted in the client.
+const SORT_PAGE_SIZE: usize = 100;
 
 #[derive(serde::Deserialize, Debug)]
 struct SearchResult {
@@ -20,42 +25,91 @@ struct SearchResult {
     summaries: Option<Vec<Summary>>,
 }
 
-pub async fn run(options: SearchOptions) -> anyhow::Result<()> {
-    let (term_width, term_height) = match terminal_size::terminal_size() {
-        Some((terminal_size::Width(w), terminal_size::Height(h))) => (w as usize, h as usize),
-        None => (80, 25),
+pub async fn run(
+    options: SearchOptions,
+    backend: &Arc<dyn Backend>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let term_height = match terminal_size::terminal_size() {
+        Some((_, terminal_size::Height(h))) => h as usize,
+        None => 25,
     };
 
-    let show_prompt = atty::is(atty::Stream::Stdout) && atty::is(atty::Stream::Stdin);
+    let json = format == OutputFormat::Json;
 
-    let description_width = term_width - /* column widths */ 5 - 31 - 18 - 7 - 7;
+    let show_prompt = !json && atty::is(atty::Stream::Stdout) && atty::is(atty::Stream::Stdin);
+
+    let mut table = results_table();
+
+    if let Some(columns) = &options.columns {
+        table.select(columns)?;
+    }
 
-    let limit = options.limit.unwrap_or(usize::MAX);
     let terms = options.terms.join(" ");
+    let mut query_params = search_filters(&options);
     let search_url = options.search_url;
 
-    macro_rules! row {
-        ($($values:tt)*) => {
-            println!(
-                "{:4} {:30.30} {:dw$.dw$} {:>17.17} {:>6.6} {:>6.6}",
-                $($values)*,
-                dw = description_width
-            )
+    // Results sorted by the server are shown as usual. Any other sort
+    // requires to download all results before showing them.
+    let client_sort = match options.sort {
+        Some(SearchSort::Updated) => {
+            query_params.push(("sort", "updated_at".to_string()));
+            query_params.push(("order", "desc".to_string()));
+            None
         }
-    }
 
-    let (pages_tx, mut pages_rx) = mpsc::channel(PAGES_QUEUE_SIZE);
-    tokio::spawn(async move {
-        pages_queue(pages_tx, search_url, terms, term_height - 2)
-            .await
-            .unwrap()
-    });
+        sort => sort,
+    };
+
+    let limit = match (options.limit, client_sort) {
+        (Some(limit), _) => limit,
+        (None, Some(_)) => DEFAULT_SORT_LIMIT,
+        (None, None) => usize::MAX,
+    };
+
+    let page_size = term_height.saturating_sub(2).max(1);
+
+    // The output is sent to the pager only when the prompt is not used.
+    let mut output = if show_prompt {
+        crate::pager::stdout()
+    } else {
+        crate::pager::open(&crate::pager::Context {
+            command: "search",
+            query: Some(&terms),
+            ..Default::default()
+        })
+    };
+
+    let download_size = if client_sort.is_some() {
+        SORT_PAGE_SIZE
+    } else {
+        page_size
+    };
+
+    let mut pages = search_pages(
+        Arc::clone(backend),
+        search_url,
+        &terms,
+        query_params,
+        download_size,
+    )?;
+
+    if let Some(sort) = client_sort {
+        pages = sort_pages(pages, sort, limit, page_size);
+    }
 
     let mut repositories_found = vec![];
 
-    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut layout = None;
+
+    let mut prompt = if show_prompt {
+        Some(crate::prompt::Prompt::new())
+    } else {
+        None
+    };
 
-    'main: while let Some(results) = pages_rx.recv().await {
+    'main: while let Some(results) = pages.next().await {
+        let results = results?;
         let total_rows = results.count;
         let summaries = match results.summaries {
             Some(s) if !s.is_empty() => s,
@@ -64,19 +118,24 @@ pub async fn run(options: SearchOptions) -> anyhow::Result<()> {
 
         // Print current page.
 
-        if show_prompt || repositories_found.is_empty() {
-            row!("", "IMAGE", "DESCRIPTION", "LAST UPDATE", "PULLS", "STARS");
-        }
+        let print_header = show_prompt || repositories_found.is_empty();
 
+        table.clear();
         for summary in summaries {
-            row!(
-                repositories_found.len() + 1,
-                summary.slug,
-                first_line(summary.short_description.as_ref(), Some(description_width)),
-                summary.updated_at.to_human(),
-                first_line(summary.pull_count.as_ref(), None),
-                summary.star_count.unwrap_or(0)
-            );
+            if json {
+                serde_json::to_writer(&mut output, &summary)?;
+                writeln!(output)?;
+            } else {
+                table.push(vec![
+                    (repositories_found.len() + 1).to_string(),
+                    summary.slug.clone(),
+                    summary.trust_label().to_string(),
+                    first_line(summary.short_description.as_ref()),
+                    summary.updated_at.to_human(),
+                    first_line(summary.pull_count.as_ref()),
+                    summary.star_count.unwrap_or(0).to_string(),
+                ]);
+            }
 
             repositories_found.push(summary);
 
@@ -85,23 +144,33 @@ pub async fn run(options: SearchOptions) -> anyhow::Result<()> {
             }
         }
 
+        // Keep the same layout for all pages.
+        if !json {
+            let layout = layout.get_or_insert_with(|| table.layout(terminal_width(), true));
+            table.render_layout(&mut output, layout, print_header)?;
+        }
+
         // Get input from user.
 
-        loop {
-            if !show_prompt {
-                break;
-            }
+        output.flush()?;
 
-            print!(
-                "[Found {} results] <ENTER>: more results | Number and <ENTER>: image details > ",
+        while let Some(prompt) = &mut prompt {
+            prompt.set_results(repositories_found.len());
+
+            let message = format!(
+                "[Found {} results] <ENTER>: more results | Number and <ENTER>: image details | q: quit > ",
                 total_rows
             );
 
-            std::io::stdout().flush()?;
+            let line = match tokio::task::block_in_place(|| prompt.read_line(&message))? {
+                Some(line) => line,
+                None => break 'main,
+            };
 
-            let line = stdin.next_line().await?.unwrap_or_default();
             let number: usize = match line.trim() {
-                "" => break,
+                "" | "n" | "next" => break,
+
+                "q" | "quit" => break 'main,
 
                 l => match usize::from_str(l) {
                     Ok(n) => n,
@@ -121,7 +190,7 @@ pub async fn run(options: SearchOptions) -> anyhow::Result<()> {
                 }
             };
 
-            crate::show::show_repository_by_slug(&repository.slug).await?;
+            crate::show::show_repository_by_slug(&**backend, &repository.slug).await?;
             break 'main;
         }
 
@@ -130,89 +199,181 @@ pub async fn run(options: SearchOptions) -> anyhow::Result<()> {
         }
     }
 
-    Ok(())
+    output.finish()
 }
 
-/// Extract the first line of `value`.
-///
-/// If `value` is `None`, returns an empty string.
-///
-/// If `width` is defined, the text will be wrapped to it.
-fn first_line<T>(value: Option<T>, width: Option<usize>) -> String
-where
-    T: std::fmt::Display,
-{
-    let value = match value {
-        Some(v) => v.to_string(),
-        None => return String::new(),
-    };
+/// Collect up to `limit` results from `pages`, and send them sorted by
+/// `sort` in pages of `page_size` results.
+fn sort_pages(
+    mut pages: Pages<SearchResult>,
+    sort: SearchSort,
+    limit: usize,
+    page_size: usize,
+) -> Pages<SearchResult> {
+    let (mut sorted_tx, sorted) = pages::channel();
+
+    tokio::spawn(async move {
+        let mut count = 0;
+        let mut summaries = Vec::new();
+
+        while summaries.len() < limit {
+            match pages.next().await {
+                Some(Ok(SearchResult {
+                    count: c,
+                    summaries: Some(s),
+                })) if !s.is_empty() => {
+                    count = c;
+                    summaries.extend(s);
+                }
+
+                Some(Err(e)) => {
+                    let _ = sorted_tx.send(Err(e)).await;
+                    return;
+                }
 
-    match (width, value.split('\n').next()) {
-        (_, None) => String::new(),
+                _ => break,
+            }
+        }
 
-        (None, Some(v)) => v.into(),
+        // Stop the download of new pages.
+        drop(pages);
 
-        (Some(width), Some(value)) => {
-            let mut lines = textwrap::wrap(value, width - 2).into_iter();
-            let first_line = lines.next().unwrap_or_default();
+        summaries.truncate(limit);
+        sort_summaries(&mut summaries, sort);
 
-            if lines.next().is_some() {
-                // Append '…' to the result if there are more than one line.
-                format!("{} …", first_line)
-            } else {
-                first_line.to_string()
+        while !summaries.is_empty() {
+            let rest = summaries.split_off(page_size.min(summaries.len()));
+            let page = SearchResult {
+                count,
+                summaries: Some(summaries),
+            };
+
+            if sorted_tx.send(Ok(page)).await.is_err() {
+                return;
             }
+
+            summaries = rest;
         }
+    });
+
+    sorted
+}
+
+/// Table to print the search results.
+fn results_table() -> Table {
+    Table::new(vec![
+        Column::new("number", "").right().priority(10),
+        Column::new("image", "IMAGE")
+            .shrink(16)
+            .max_width(40)
+            .priority(9),
+        Column::new("trust", "").priority(3),
+        Column::new("description", "DESCRIPTION")
+            .shrink(12)
+            .priority(8),
+        Column::new("updated", "LAST UPDATE").right().priority(5),
+        Column::new("pulls", "PULLS").right().priority(4),
+        Column::new("stars", "STARS").right().priority(6),
+    ])
+}
+
+/// Sort summaries in descending order.
+fn sort_summaries(summaries: &mut [Summary], sort: SearchSort) {
+    match sort {
+        SearchSort::Pulls => summaries.sort_by_key(|s| std::cmp::Reverse(s.pulls().unwrap_or(0))),
+        SearchSort::Stars => {
+            summaries.sort_by_key(|s| std::cmp::Reverse(s.star_count.unwrap_or(0)))
+        }
+        SearchSort::Updated => summaries.sort_by_key(|s| std::cmp::Reverse(s.updated_at)),
+    }
+}
+
+/// Build the query parameters for the filters in the command-line options.
+fn search_filters(options: &SearchOptions) -> Vec<(&'static str, String)> {
+    let mut filters = vec![];
+
+    let image_filter: Vec<_> = [(options.official, "official"), (options.verified, "store")]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| *name)
+        .collect();
+
+    if !image_filter.is_empty() {
+        filters.push(("image_filter", image_filter.join(",")));
     }
+
+    let values = [
+        ("operating_system", &options.operating_system),
+        ("architecture", &options.architecture),
+        ("category", &options.category),
+    ];
+
+    for (param, value) in values.iter() {
+        if let Some(value) = value {
+            filters.push((*param, value.clone()));
+        }
+    }
+
+    filters
 }
 
-/// Background task to download pages when the program is waiting for user
-/// input.
+/// Extract the first line of `value`.
 ///
-/// The queue is bounde to PAGES_QUEUE_SIZE, so we will not download a lot of
-/// unneeded pages.
-async fn pages_queue(
-    mut pages_tx: mpsc::Sender<SearchResult>,
+/// If `value` is `None`, returns an empty string.
+fn first_line<T>(value: Option<T>) -> String
+where
+    T: std::fmt::Display,
+{
+    match value {
+        Some(v) => v.to_string().lines().next().unwrap_or_default().to_string(),
+        None => String::new(),
+    }
+}
+
+/// Start the download of the search results, using page numbers.
+///
+/// Pages are requested until the server returns an empty page.
+fn search_pages(
+    backend: Arc<dyn Backend>,
     search_url: Option<String>,
-    terms: String,
+    terms: &str,
+    query_params: Vec<(&'static str, String)>,
     per_page: usize,
-) -> anyhow::Result<()> {
-    let per_page = format!("{}", per_page);
-
-    let search_url = search_url
-        .as_ref()
-        .map(|s| &s[..])
-        .unwrap_or(DEFAULT_SEARCH_URL);
-
-    let mut num_page: usize = 1;
-
-    let http_client = crate::hubapi::http_client()?;
-
-    loop {
-        let np_str = num_page.to_string();
-
-        let result = http_client
-            .get(search_url)
-            .header("Accept", "application/json")
-            .header("Search-Version", "v3")
-            .query(&[
-                ("type", "image"),
-                ("q", terms.as_str()),
-                ("page_size", per_page.as_str()),
-                ("page", np_str.as_str()),
-            ])
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        if pages_tx.send(result).await.is_err() {
-            // Receiver is closed
-            return Ok(());
-        }
+) -> anyhow::Result<Pages<SearchResult>> {
+    let mut search_url = reqwest::Url::parse(search_url.as_deref().unwrap_or(DEFAULT_SEARCH_URL))?;
+
+    search_url
+        .query_pairs_mut()
+        .append_pair("type", "image")
+        .append_pair("q", terms)
+        .append_pair("page_size", &per_page.to_string())
+        .extend_pairs(&query_params);
+
+    let page_url = move |num_page: usize| {
+        let mut url = search_url.clone();
+        url.query_pairs_mut()
+            .append_pair("page", &num_page.to_string());
+        url.to_string()
+    };
 
-        num_page += 1;
-    }
+    let first = page_url(1);
+    let mut num_page = 1;
+
+    let pages = pages::prefetch(
+        backend,
+        &[("Search-Version", "v3")],
+        first,
+        move |result: &SearchResult| match &result.summaries {
+            Some(s) if !s.is_empty() => {
+                num_page += 1;
+                Some(page_url(num_page))
+            }
+
+            _ => None,
+        },
+    );
+
+    Ok(pages)
 }
 
 #[cfg(target_os = "linux")]
@@ -225,12 +386,17 @@ fn search_apache_repository() {
 
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
     cmd.env(crate::pager::PAGER_ENV, "cat");
-    cmd.args(&["search", "apache"]);
-    let mut cmd = spawn_command(cmd, Some(60_000)).unwrap();
+    cmd.args([
+        "--replay",
+        crate::backend::TEST_FIXTURES,
+        "search",
+        "apache",
+    ]);
+    let mut cmd = spawn_command(cmd, Some(10_000)).unwrap();
 
     // Find "httpd" repository
 
-    let (_, line) = cmd.exp_regex("\n +[0-9]+ +httpd ").unwrap();
+    let (_, line) = cmd.exp_regex("\n *[0-9]+ +httpd ").unwrap();
     let repostiory_number = line.split_whitespace().next().unwrap();
     cmd.send_line(repostiory_number).unwrap();
 
@@ -238,7 +404,48 @@ fn search_apache_repository() {
 
     cmd.exp_string("Namespace: library").unwrap();
     cmd.exp_string("Name: httpd").unwrap();
-    cmd.exp_string("# Quick reference").unwrap();
+    cmd.exp_string("Quick reference").unwrap();
 
     assert!(matches!(cmd.process.wait(), Ok(Exited(_, 0))));
 }
+
+#[test]
+fn replay_search_to_completion() {
+    use std::time::Duration;
+
+    // Pages are requested until the replayed search returns an empty page.
+    let process = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--no-pager", "--replay", crate::backend::TEST_FIXTURES])
+        .args(["search", "--columns", "image", "apache"])
+        .timeout(Duration::from_secs(10))
+        .unwrap();
+
+    let output = String::from_utf8(process.stdout).unwrap();
+    let lines: Vec<_> = output.lines().map(str::trim_end).collect();
+    assert_eq!(lines, ["IMAGE", "httpd", "tomcat", "bitnami/apache"]);
+}
+
+#[test]
+fn render_results_in_narrow_terminals() {
+    let mut table = results_table();
+    table.push(vec![
+        "1".into(),
+        "bitnami/apache-exporter".into(),
+        "verified".into(),
+        "Prometheus exporter for Apache metrics".into(),
+        "2 days ago".into(),
+        "1B+".into(),
+        "4000".into(),
+    ]);
+
+    let mut output = Vec::new();
+    table.render(&mut output, Some(60), true).unwrap();
+
+    // The trust column is hidden, and the description is kept.
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "   IMAGE             DESCRIPTION   LAST UPDATE  PULLS  STARS\n\
+         1  bitnami/apache-…  Prometheus …   2 days ago    1B+   4000\n"
+    );
+}
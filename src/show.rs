@@ -1,46 +1,68 @@
 //! Implementation of the 'show' command
-//!
-//! The pager will receive the following environment variables:
-//!
-//! * `HDCQUERY_REPOSITORY`: name of the repository, like `"library/rust"`.
-//! * `HDCQUERY_VERSION`: version of hdcquery.
 
+use std::io::Write;
+use std::sync::Arc;
+
+use futures::stream::StreamExt;
+
+use crate::backend::Backend;
 use crate::hubapi::Repository;
 use crate::langext::DurationExt;
-use crate::options::ShowOptions;
+use crate::options::{OutputFormat, ShowOptions};
+use crate::reference::{self, Reference};
 
 const REPOSITORY_URL: &str = "https://hub.docker.com/v2/repositories/";
 
-pub async fn run(options: ShowOptions) -> anyhow::Result<()> {
+pub async fn run(
+    options: ShowOptions,
+    backend: &Arc<dyn Backend>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
     if options.repositories.is_empty() {
         eprintln!("No repositories");
         return Ok(());
     }
 
-    for repository in &options.repositories {
-        let repository = get_repository(&repository).await?;
-        if options.only_description {
+    let mut repositories = reference::fetch_all(&options.repositories, options.jobs, |r| {
+        get_repository(&**backend, r)
+    });
+
+    while let Some(repository) = repositories.next().await {
+        let repository = repository?;
+        if format == OutputFormat::Json {
+            println!("{}", serde_json::to_string(&repository)?);
+        } else if options.only_description {
             if let Some(full_description) = repository.full_description {
                 println!("{}", full_description);
             }
         } else {
-            show_repository(&repository).await?
+            show_repository(&repository, options.raw).await?
         }
     }
 
     Ok(())
 }
 
-pub async fn show_repository_by_slug(slug: &str) -> anyhow::Result<()> {
-    show_repository(&get_repository(slug).await?).await
+pub async fn show_repository_by_slug(backend: &dyn Backend, slug: &str) -> anyhow::Result<()> {
+    let reference: Reference = slug.parse().map_err(anyhow::Error::msg)?;
+    show_repository(&get_repository(backend, &reference).await?, false).await
 }
 
-pub async fn show_repository(repository: &Repository) -> anyhow::Result<()> {
-    let pager_cmd;
-    let mut pager_stdin;
-    let mut io_stdout;
+/// Write the repository details to the pager.
+///
+/// The full description is rendered as Markdown, unless `raw_description`
+/// is `true`.
+pub async fn show_repository(repository: &Repository, raw_description: bool) -> anyhow::Result<()> {
+    let slug = match (&repository.namespace, &repository.name) {
+        (Some(ns), Some(n)) => Some(format!("{}/{}", ns, n)),
+        _ => None,
+    };
 
-    let output: &mut dyn std::io::Write;
+    let mut output = crate::pager::open(&crate::pager::Context {
+        command: "show",
+        repository: slug.as_deref(),
+        ..Default::default()
+    });
 
     macro_rules! option_field {
         ($field:ident, $label:literal) => {
@@ -54,24 +76,6 @@ pub async fn show_repository(repository: &Repository) -> anyhow::Result<()> {
         };
     }
 
-    let slug = match (&repository.namespace, &repository.name) {
-        (Some(ns), Some(n)) => Some(format!("{}/{}", ns, n)),
-        _ => None,
-    };
-
-    if let Some(mut cmd) = crate::pager::command(slug.as_deref()) {
-        let mut child = cmd.spawn()?;
-        pager_stdin = child.stdin.take();
-        pager_cmd = Some(child);
-
-        output = pager_stdin.as_mut().expect("stdin expected for pager");
-    } else {
-        pager_cmd = None;
-        pager_stdin = None;
-        io_stdout = std::io::stdout();
-        output = &mut io_stdout;
-    }
-
     option_field!(namespace, "Namespace");
     option_field!(name, "Name");
     option_field!(description, "Description");
@@ -94,36 +98,47 @@ pub async fn show_repository(repository: &Repository) -> anyhow::Result<()> {
     }
 
     if let Some(full_description) = &repository.full_description {
+        let full_description = if raw_description {
+            full_description.trim_end().to_string()
+        } else {
+            let width = match terminal_size::terminal_size() {
+                Some((terminal_size::Width(w), _)) => w as usize,
+                None => 80,
+            };
+
+            let ansi = output.supports_ansi();
+            crate::markdown::render(full_description, width, ansi)
+                .trim_end()
+                .to_string()
+        };
+
         writeln!(output, "\n----\n\n{}\n\n----", full_description)?;
     }
 
-    // Close the pager's input
-    drop(pager_stdin);
-    let _ = pager_cmd.map(|mut c| c.wait());
-
-    Ok(())
+    output.finish()
 }
 
 /// Download the repository data.
 ///
-/// If `slug` does not contain a '/', it will be prepended with "library/".
-/// This is the expected format by hub.docker.com
-async fn get_repository(slug: &str) -> Result<Repository, reqwest::Error> {
-    let full_url = if slug.contains('/') {
-        format!("{}{}/", REPOSITORY_URL, slug)
-    } else {
-        format!("{}library/{}/", REPOSITORY_URL, slug)
-    };
+/// Repositories outside Docker Hub are downloaded with the OCI distribution
+/// API, which provides less data.
+async fn get_repository(
+    backend: &dyn Backend,
+    reference: &Reference,
+) -> anyhow::Result<Repository> {
+    if !reference.is_docker_hub() {
+        return crate::registry::Registry::new(backend, reference)?
+            .repository()
+            .await;
+    }
 
-    let http_client = crate::hubapi::http_client()?;
+    let full_url = format!("{}{}/", REPOSITORY_URL, reference.path);
 
-    http_client
-        .get(&full_url)
-        .header("Accept", "application/json")
-        .send()
+    backend
+        .get(&full_url, &[("Accept", "application/json")])
         .await?
+        .error_for_status()?
         .json()
-        .await
 }
 
 #[test]
@@ -133,7 +148,8 @@ fn show_rust_repository() {
 
     let process = {
         let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
-        cmd.args(&["show", "rustlang/rust"]);
+        cmd.args(["--no-pager", "--replay", crate::backend::TEST_FIXTURES]);
+        cmd.args(["show", "rustlang/rust"]);
         cmd.unwrap()
     };
 
@@ -141,6 +157,8 @@ fn show_rust_repository() {
 
     assert!(stdout.contains("Namespace: rustlang"));
     assert!(stdout.contains("Name: rust"));
-    assert!(stdout.contains("---"));
+    assert!(stdout.contains("Last updated: 2020-12-12 10:20 UTC"));
+    assert!(stdout.contains("Images with the nightly toolchain. See the official images[1]."));
+    assert!(stdout.contains("[1] https://hub.docker.com/_/rust"));
     assert!(process.status.code() == Some(0));
 }
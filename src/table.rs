@@ -0,0 +1,340 @@
+//! Render tabular data in the terminal.
+//!
+//! The width of every column is computed from its contents. If the table
+//! does not fit in the terminal, the columns with the lowest priority are
+//! hidden until the rest fit in their minimum widths, and then the columns
+//! are shrunk to use the available width.
+//!
+//! Widths are measured as the number of cells used in the terminal, so
+//! wide characters (like CJK) are aligned correctly.
+
+use std::io::{self, Write};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::langext::StrExt;
+
+/// Separator between columns.
+const GAP: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug)]
+pub struct Column {
+    /// Identifier for the `--columns` option.
+    pub id: &'static str,
+
+    pub header: &'static str,
+
+    pub align: Align,
+
+    /// Minimum width when the column is shrunk. By default, columns are not
+    /// shrunk.
+    pub min_width: usize,
+
+    /// Maximum width of the column.
+    pub max_width: usize,
+
+    /// Columns with lower priority are hidden first.
+    pub priority: u8,
+}
+
+impl Column {
+    pub fn new(id: &'static str, header: &'static str) -> Column {
+        Column {
+            id,
+            header,
+            align: Align::Left,
+            min_width: usize::MAX,
+            max_width: usize::MAX,
+            priority: 0,
+        }
+    }
+
+    pub fn right(mut self) -> Column {
+        self.align = Align::Right;
+        self
+    }
+
+    pub fn shrink(mut self, min_width: usize) -> Column {
+        self.min_width = min_width;
+        self
+    }
+
+    pub fn max_width(mut self, max_width: usize) -> Column {
+        self.max_width = max_width;
+        self
+    }
+
+    pub fn priority(mut self, priority: u8) -> Column {
+        self.priority = priority;
+        self
+    }
+}
+
+/// Visible columns, and their widths.
+pub struct Layout(Vec<(usize, usize)>);
+
+pub struct Table {
+    columns: Vec<Column>,
+
+    /// Indices of the columns to render.
+    visible: Vec<usize>,
+
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(columns: Vec<Column>) -> Table {
+        let visible = (0..columns.len()).collect();
+        Table {
+            columns,
+            visible,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Select the columns to render, from a comma-separated list of
+    /// identifiers.
+    pub fn select(&mut self, ids: &str) -> anyhow::Result<()> {
+        let mut visible = Vec::new();
+
+        for id in ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+            match self.columns.iter().position(|c| c.id == id) {
+                Some(index) => visible.push(index),
+                None => {
+                    let available: Vec<_> = self.columns.iter().map(|c| c.id).collect();
+                    anyhow::bail!(
+                        "Invalid column {:?}. Available columns: {}",
+                        id,
+                        available.join(",")
+                    );
+                }
+            }
+        }
+
+        self.visible = visible;
+        Ok(())
+    }
+
+    /// Hide a column, even if it was selected with `select`.
+    pub fn hide(&mut self, id: &str) {
+        if let Some(index) = self.columns.iter().position(|c| c.id == id) {
+            self.visible.retain(|v| *v != index);
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    /// Write the table to `output`, limited to `max_width` cells per line.
+    pub fn render(
+        &self,
+        output: &mut dyn Write,
+        max_width: Option<usize>,
+        header: bool,
+    ) -> io::Result<()> {
+        let layout = self.layout(max_width, header);
+        self.render_layout(output, &layout, header)
+    }
+
+    /// Write the table to `output`, with the column widths computed in a
+    /// previous call to `layout`.
+    ///
+    /// This is used to keep the columns aligned when a table is printed in
+    /// multiple pages.
+    pub fn render_layout(
+        &self,
+        output: &mut dyn Write,
+        layout: &Layout,
+        header: bool,
+    ) -> io::Result<()> {
+        let mut line = String::new();
+
+        let headers: Vec<_> = self.columns.iter().map(|c| c.header.to_string()).collect();
+        let headers = if header { Some(&headers) } else { None };
+
+        for row in headers.into_iter().chain(&self.rows) {
+            line.clear();
+
+            for (position, (index, width)) in layout.0.iter().enumerate() {
+                let column = &self.columns[*index];
+                let cell = row.get(*index).map(|c| c.as_str()).unwrap_or_default();
+                let cell = truncate(cell, *width);
+                let padding = width - cell.display_width();
+
+                if position > 0 {
+                    line.push_str(&" ".repeat(GAP));
+                }
+
+                match column.align {
+                    Align::Left => {
+                        line.push_str(&cell);
+                        line.push_str(&" ".repeat(padding));
+                    }
+
+                    Align::Right => {
+                        line.push_str(&" ".repeat(padding));
+                        line.push_str(&cell);
+                    }
+                }
+            }
+
+            writeln!(output, "{}", line.trim_end())?;
+        }
+
+        Ok(())
+    }
+
+    /// Compute the visible columns and their widths.
+    pub fn layout(&self, max_width: Option<usize>, header: bool) -> Layout {
+        let max_width = max_width.unwrap_or(usize::MAX);
+
+        let mut layout: Vec<(usize, usize)> = self
+            .visible
+            .iter()
+            .map(|&index| {
+                let column = &self.columns[index];
+
+                let cells = self.rows.iter().filter_map(|r| r.get(index));
+                let content = cells.map(|c| c.display_width()).max().unwrap_or(0);
+                let header = if header {
+                    column.header.display_width()
+                } else {
+                    0
+                };
+
+                (index, content.max(header).min(column.max_width))
+            })
+            .collect();
+
+        let total = |layout: &[(usize, usize)]| -> usize {
+            let widths: usize = layout.iter().map(|(_, w)| w).sum();
+            widths + GAP * layout.len().saturating_sub(1)
+        };
+
+        let min_total = |layout: &[(usize, usize)]| -> usize {
+            let widths: usize = layout
+                .iter()
+                .map(|&(index, width)| self.columns[index].min_width.min(width))
+                .sum();
+            widths + GAP * layout.len().saturating_sub(1)
+        };
+
+        // Hide columns, starting with the lowest priority, until the rest
+        // fit in their minimum widths.
+        while min_total(&layout) > max_width && layout.len() > 1 {
+            let lowest = (0..layout.len())
+                .min_by_key(|&i| self.columns[layout[i].0].priority)
+                .unwrap();
+
+            layout.remove(lowest);
+        }
+
+        // Shrink columns, starting with the lowest priority, only as much as
+        // needed, so the width of the hidden columns goes to the rest.
+        let mut by_priority: Vec<_> = (0..layout.len()).collect();
+        by_priority.sort_by_key(|&i| self.columns[layout[i].0].priority);
+
+        for &position in &by_priority {
+            let excess = total(&layout).saturating_sub(max_width);
+            if excess == 0 {
+                break;
+            }
+
+            let (index, width) = &mut layout[position];
+            let min_width = self.columns[*index].min_width.min(*width);
+            *width -= excess.min(*width - min_width);
+        }
+
+        Layout(layout)
+    }
+}
+
+/// Width of the terminal, if stdout is connected to one.
+pub fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w as usize)
+}
+
+/// Truncate `text` to `width` cells, adding a '…' if needed.
+fn truncate(text: &str, width: usize) -> String {
+    if text.display_width() <= width {
+        return text.to_string();
+    }
+
+    let mut output = String::new();
+    let mut output_width = 0;
+
+    for c in text.strip_ansi().chars() {
+        let char_width = c.width().unwrap_or(0);
+        if output_width + char_width + 1 > width {
+            break;
+        }
+
+        output.push(c);
+        output_width += char_width;
+    }
+
+    if width > 0 {
+        output.push('…');
+    }
+
+    output
+}
+
+#[test]
+fn render_tables() {
+    let mut table = Table::new(vec![
+        Column::new("name", "NAME").priority(9),
+        Column::new("description", "DESCRIPTION")
+            .shrink(8)
+            .priority(2),
+        Column::new("stars", "STARS").right().priority(1),
+    ]);
+
+    table.push(vec![
+        "redis".into(),
+        "Redis is an open source key-value store".into(),
+        "10".into(),
+    ]);
+    table.push(vec!["日本語".into(), "Short".into(), "5".into()]);
+
+    let render = |table: &Table, width| {
+        let mut output = Vec::new();
+        table.render(&mut output, Some(width), true).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(
+        render(&table, 80),
+        "NAME    DESCRIPTION                              STARS\n\
+         redis   Redis is an open source key-value store     10\n\
+         日本語  Short                                        5\n"
+    );
+
+    assert_eq!(
+        render(&table, 30),
+        "NAME    DESCRIPTION      STARS\n\
+         redis   Redis is an op…     10\n\
+         日本語  Short                5\n"
+    );
+
+    assert_eq!(render(&table, 12), "NAME\nredis\n日本語\n");
+
+    table.select("stars,name").unwrap();
+    assert_eq!(
+        render(&table, 80),
+        "STARS  NAME\n   10  redis\n    5  日本語\n"
+    );
+
+    assert!(table.select("name,unknown").is_err());
+}
@@ -1,134 +1,989 @@
 //! Implementation of the 'tags' command
 
-use crate::hubapi::Tag;
+use std::collections::hash_map::{Entry, HashMap};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use regex::Regex;
+
+use crate::backend::Backend;
+use crate::hubapi::{Image, Tag};
 use crate::langext::DurationExt;
-use crate::options::TagsOptions;
-
-const DEFAULT_OS: Option<&str> = if cfg!(target_os = "linux") {
-    Some("linux")
-} else if cfg!(target_os = "windows") {
-    Some("windows")
-} else {
-    None
-};
-
-const DEFAULT_ARCH: Option<&str> = if cfg!(target_arch = "x86_64") {
-    Some("amd64")
-} else if cfg!(target_arch = "x86") {
-    Some("386")
-} else if cfg!(target_arch = "arm") {
-    Some("arm")
-} else if cfg!(target_arch = "aarch64") {
-    Some("arm64")
-} else {
-    None
-};
+use crate::options::{OutputFormat, PlatformArg, TagsOptions};
+use crate::pages;
+use crate::platform::{self, Platform};
+use crate::reference::{self, Reference};
+use crate::registry::Registry;
+use crate::table::{terminal_width, Column, Table};
+
+/// Maximum page size accepted by hub.docker.com.
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Default value for `--limit`.
+const DEFAULT_LIMIT: usize = 30;
+
+/// Number of tags to download concurrently from an OCI registry.
+const REGISTRY_TAGS_JOBS: usize = 4;
 
 #[derive(serde::Deserialize, Debug)]
 struct Response {
     count: usize,
+    next: Option<String>,
     results: Vec<Tag>,
 }
 
-pub async fn run(options: TagsOptions) -> anyhow::Result<()> {
-    macro_rules! row {
-        ($size:expr, $os:expr, $arch:expr, $push:expr, $digest:expr, $name:expr) => {
-            if options.digest {
-                println!(
-                    "{:10} {:8.8} {:6.6} {:14} {:73} {}",
-                    $size, $os, $arch, $push, $digest, $name
-                )
-            } else {
-                println!(
-                    "{:10} {:8.8} {:6.6} {:15} {}",
-                    $size, $os, $arch, $push, $name
-                )
+/// How to display the tags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    /// One row for every image.
+    Images,
+
+    /// One row for every tag, with the list of its platforms.
+    Group,
+
+    /// Every tag, followed by its images.
+    Tree,
+
+    /// One row for every group of tags with the same images.
+    Aliases,
+}
+
+impl FromStr for View {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "images" => Ok(View::Images),
+            "group" => Ok(View::Group),
+            "tree" => Ok(View::Tree),
+            "aliases" => Ok(View::Aliases),
+            _ => Err(format!("invalid view {:?}", s)),
+        }
+    }
+}
+
+impl View {
+    fn table(self) -> Table {
+        match self {
+            View::Images => Table::new(vec![
+                Column::new("size", "SIZE").right().priority(8),
+                Column::new("os", "OS").priority(6),
+                Column::new("os-version", "OS VERSION").priority(3),
+                Column::new("arch", "ARCH").priority(7),
+                Column::new("pushed", "LAST PUSHED").priority(5),
+                Column::new("digest", "DIGEST").shrink(19).priority(4),
+                Column::new("name", "NAME").shrink(10).priority(10),
+                Column::new("attestations", "ATTESTATIONS").priority(2),
+            ]),
+
+            View::Group => Table::new(vec![
+                Column::new("name", "NAME").shrink(10).priority(10),
+                Column::new("platforms", "PLATFORMS").shrink(12).priority(8),
+                Column::new("size", "SIZE").right().priority(6),
+                Column::new("pushed", "LAST PUSHED").priority(7),
+                Column::new("attestations", "ATTESTATIONS").priority(3),
+            ]),
+
+            View::Tree => Table::new(vec![
+                Column::new("platform", "PLATFORM").priority(10),
+                Column::new("size", "SIZE").right().priority(8),
+                Column::new("digest", "DIGEST").shrink(19).priority(6),
+            ]),
+
+            View::Aliases => Table::new(vec![
+                Column::new("tags", "TAGS").shrink(16).priority(10),
+                Column::new("platforms", "PLATFORMS").shrink(12).priority(8),
+                Column::new("size", "SIZE").right().priority(6),
+                Column::new("pushed", "LAST PUSHED").priority(7),
+                Column::new("attestations", "ATTESTATIONS").priority(3),
+            ]),
+        }
+    }
+}
+
+/// Filters for tags and images.
+struct Filter<'a> {
+    os: Option<&'a str>,
+    arch: Option<&'a str>,
+    variant: Option<&'a str>,
+    attestations: bool,
+    since: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+    name_match: Option<&'a Regex>,
+    exclude: Option<&'a Regex>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+}
+
+impl Filter<'_> {
+    /// Check if there is any filter for the images of a tag.
+    fn filters_images(&self) -> bool {
+        self.os.is_some()
+            || self.arch.is_some()
+            || self.min_size.is_some()
+            || self.max_size.is_some()
+    }
+
+    fn matches_tag(&self, tag: &Tag) -> bool {
+        if let Some(re) = self.name_match {
+            if !re.is_match(&tag.name) {
+                return false;
             }
-        };
+        }
+
+        if let Some(re) = self.exclude {
+            if re.is_match(&tag.name) {
+                return false;
+            }
+        }
+
+        if self.since.is_some() || self.before.is_some() {
+            let last_updated = match tag.last_updated {
+                Some(lu) => lu,
+                None => return false,
+            };
+
+            if matches!(self.since, Some(since) if last_updated < since)
+                || matches!(self.before, Some(before) if last_updated >= before)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Check if the tag was pushed before `since`.
+    fn is_too_old(&self, tag: &Tag) -> bool {
+        matches!((self.since, tag.last_updated), (Some(since), Some(lu)) if lu < since)
     }
 
-    let filter_os;
-    let filter_arch;
+    fn matches_image(&self, image: &Image) -> bool {
+        (self.os.is_none() || self.os == Some(image.os.as_str()))
+            && (self.arch.is_none() || self.arch == Some(image.architecture.as_str()))
+            && (self.variant.is_none() || self.variant == image_variant(image))
+            && (self.attestations || !image.is_attestation())
+            && !matches!(self.min_size, Some(min) if image.size < min)
+            && !matches!(self.max_size, Some(max) if image.size > max)
+    }
+}
+
+pub async fn run(
+    options: TagsOptions,
+    backend: &Arc<dyn Backend>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let json = format == OutputFormat::Json;
+
+    // With JSON, every tag is a result, like in the `Group` view.
+    let view = if json {
+        View::Group
+    } else if options.aliases {
+        View::Aliases
+    } else if options.tree {
+        View::Tree
+    } else if options.group {
+        View::Group
+    } else {
+        options.view.unwrap_or(View::Images)
+    };
+
+    let slugs: Vec<_> = options.repositories.iter().map(|r| r.full_name()).collect();
+
+    let mut output = crate::pager::open(&crate::pager::Context {
+        command: "tags",
+        repository: Some(&slugs.join(" ")),
+        ..Default::default()
+    });
+
+    let mut table = view.table();
+
+    match &options.columns {
+        Some(columns) => table.select(columns)?,
+        None if view == View::Images => {
+            table.hide("os-version");
+            if !options.digest {
+                table.hide("digest");
+            }
+        }
+        None => (),
+    }
+
+    if matches!(options.platform, Some(PlatformArg::Only(_)))
+        && (options.operating_system.is_some() || options.architecture.is_some())
+    {
+        anyhow::bail!("--platform can't be used with --operating-system or --architecture");
+    }
+
+    let platform = if options.current_machine {
+        match Platform::current() {
+            Some(platform) => Some(platform),
+            None => anyhow::bail!("Unable to detect the platform of this machine"),
+        }
+    } else {
+        match &options.platform {
+            Some(PlatformArg::Only(platform)) => Some(platform.clone()),
+            Some(PlatformArg::All) | None => None,
+        }
+    };
+
+    let (os, arch, variant) = match &platform {
+        Some(p) => (
+            Some(p.os.as_str()),
+            Some(p.architecture.as_str()),
+            p.variant.as_deref(),
+        ),
+        None => (
+            options.operating_system.as_deref(),
+            options.architecture.as_deref(),
+            None,
+        ),
+    };
+
+    let filter = Filter {
+        os,
+        arch,
+        variant,
+        attestations: options.attestations,
+        since: options.since.map(|t| t.0),
+        before: options.before.map(|t| t.0),
+        name_match: options.name_match.as_ref(),
+        exclude: options.exclude.as_ref(),
+        min_size: options.min_size.map(|s| s.0),
+        max_size: options.max_size.map(|s| s.0),
+    };
+
+    let mut total_pending = options.total_limit.unwrap_or(usize::MAX);
 
-    if options.current_machine {
-        filter_os = DEFAULT_OS;
-        filter_arch = DEFAULT_ARCH;
+    let limit = if options.all {
+        usize::MAX
     } else {
-        filter_os = options.operating_system.as_deref();
-        filter_arch = options.architecture.as_deref();
+        options.limit.unwrap_or(DEFAULT_LIMIT)
     }
+    .min(total_pending);
 
-    let http_client = crate::hubapi::http_client()?;
+    let page_size = limit.clamp(1, MAX_PAGE_SIZE);
 
-    let mut pending = options.limit;
+    let mut responses = reference::fetch_all(&options.repositories, options.jobs, |r| {
+        fetch_tags(backend, r, page_size, &filter, view, limit)
+    });
 
     for repository in &options.repositories {
-        let slug_prefix = if repository.contains('/') {
-            ""
-        } else {
-            "library/"
+        // Dropping the stream cancels the requests for the rest of
+        // repositories.
+        if total_pending == 0 {
+            break;
+        }
+
+        let (count, mut tags) = match responses.next().await {
+            Some(response) => response?,
+            None => break,
         };
 
-        'repository: for page in 1.. {
-            let url = format!(
-                "https://hub.docker.com/v2/repositories/{}{}/tags/?page={}&page_size={}",
-                slug_prefix,
-                repository,
-                page,
-                options.limit.min(50)
-            );
-
-            let response: Response = http_client
-                .get(&url)
-                .header("Accept", "application/json")
-                .send()
-                .await?
-                .json()
-                .await?;
-
-            if page == 1 {
-                println!("- {} results for {}", response.count, repository);
-
-                row!("SIZE", "OS", "ARCH", "LAST PUSHED", "DIGEST", "NAME");
-            }
+        total_pending -= take_results(&mut tags, view, total_pending);
 
-            if response.results.is_empty() {
-                break;
+        if json {
+            for tag in &tags {
+                let mut value = serde_json::to_value(tag)?;
+                value["repository"] = repository.full_name().into();
+                serde_json::to_writer(&mut output, &value)?;
+                writeln!(output)?;
             }
 
-            for result in response.results {
-                let last_updated = result
-                    .last_updated
-                    .map(|lp| lp.to_human())
-                    .unwrap_or_default();
+            continue;
+        }
 
-                for image in &result.images {
-                    if pending == 0 {
-                        break 'repository;
-                    }
+        writeln!(output, "- {} results for {}", count, repository)?;
 
-                    if filter_os.is_some() && filter_os != Some(image.os.as_str()) {
-                        continue;
-                    }
+        match view {
+            View::Images => {
+                for tag in &tags {
+                    let last_updated = tag.last_updated.map(|lp| lp.to_human()).unwrap_or_default();
 
-                    if filter_arch.is_some() && filter_arch != Some(image.architecture.as_str()) {
-                        continue;
+                    for image in &tag.images {
+                        table.push(vec![
+                            bytesize::to_string(image.size, true),
+                            image.os.clone(),
+                            image.os_version.clone().unwrap_or_default(),
+                            image.architecture_variant(),
+                            last_updated.clone(),
+                            image.digest.clone().unwrap_or_default(),
+                            tag.name.clone(),
+                            attestations_label(tag),
+                        ]);
                     }
+                }
+            }
+
+            View::Group => {
+                for tag in &tags {
+                    table.push(vec![
+                        tag.name.clone(),
+                        platforms(&tag.images),
+                        size_range(&tag.images),
+                        tag.last_updated.map(|lp| lp.to_human()).unwrap_or_default(),
+                        attestations_label(tag),
+                    ]);
+                }
+            }
 
-                    pending -= 1;
+            View::Tree => {
+                write_tree(&mut output, &mut table, &tags)?;
+                continue;
+            }
 
-                    row!(
-                        bytesize::to_string(image.size, true),
-                        image.os,
-                        image.architecture,
-                        last_updated,
-                        image.digest.as_deref().unwrap_or_default(),
-                        result.name
-                    );
+            View::Aliases => {
+                for aliases in group_aliases(&tags) {
+                    let tag = aliases[0];
+                    let names: Vec<_> = aliases.iter().map(|t| t.name.as_str()).collect();
+
+                    table.push(vec![
+                        names.join(", "),
+                        platforms(&tag.images),
+                        size_range(&tag.images),
+                        tag.last_updated.map(|lp| lp.to_human()).unwrap_or_default(),
+                        attestations_label(tag),
+                    ]);
                 }
             }
         }
+
+        table.render(&mut output, terminal_width(), true)?;
+        table.clear();
+    }
+
+    output.finish()
+}
+
+/// Download the tags of a repository, until `limit` results are found.
+///
+/// Returns the number of tags in the repository, and the tags downloaded.
+async fn fetch_tags(
+    backend: &Arc<dyn Backend>,
+    reference: &Reference,
+    page_size: usize,
+    filter: &Filter<'_>,
+    view: View,
+    limit: usize,
+) -> anyhow::Result<(usize, Vec<Tag>)> {
+    let mut collector = Collector::new(reference, filter, view, limit);
+
+    let count = if reference.is_docker_hub() {
+        fetch_hub_tags(backend, reference, page_size, &mut collector).await?
+    } else {
+        fetch_registry_tags(&**backend, reference, &mut collector).await?
+    };
+
+    Ok((count, collector.tags))
+}
+
+async fn fetch_hub_tags(
+    backend: &Arc<dyn Backend>,
+    reference: &Reference,
+    page_size: usize,
+    collector: &mut Collector<'_>,
+) -> anyhow::Result<usize> {
+    let mut count = 0;
+
+    // Tags are requested by push time, newest first, so pages can be
+    // discarded after a tag older than `--since`.
+    let mut url = format!(
+        "https://hub.docker.com/v2/repositories/{}/tags/?page_size={}&ordering=last_updated",
+        reference.path, page_size
+    );
+
+    // The `name` parameter finds tags containing the value, so the exact
+    // name is checked later.
+    if let Some(tag) = &reference.tag {
+        url.push_str("&name=");
+        url.push_str(tag);
+    }
+
+    let mut pages = pages::prefetch(Arc::clone(backend), &[], url, |page: &Response| {
+        page.next.clone()
+    });
+
+    'pages: while let Some(response) = pages.next().await {
+        let response = response?;
+
+        count = response.count;
+
+        for tag in response.results {
+            // With `ordering=last_updated`, there are no more results after
+            // a tag older than `--since`.
+            if collector.is_full() || collector.filter.is_too_old(&tag) {
+                break 'pages;
+            }
+
+            // Only one of the tags found with `name` has the exact name.
+            if reference.tag.as_ref() == Some(&tag.name) {
+                collector.push(tag);
+                return Ok(1);
+            }
+
+            collector.push(tag);
+        }
+
+        if collector.is_full() {
+            break;
+        }
+    }
+
+    if reference.tag.is_some() {
+        return Ok(0);
+    }
+
+    Ok(count)
+}
+
+/// Download the tags from a registry with the OCI distribution API.
+///
+/// The registry does not provide the push time of the tags, so they are in
+/// the same order of the tags list (usually, sorted by name).
+async fn fetch_registry_tags(
+    backend: &dyn Backend,
+    reference: &Reference,
+    collector: &mut Collector<'_>,
+) -> anyhow::Result<usize> {
+    let registry = Registry::new(backend, reference)?;
+
+    let names = match &reference.tag {
+        Some(tag) => vec![tag.clone()],
+        None => registry.tag_names().await?,
+    };
+
+    let count = names.len();
+
+    let mut tags = stream::iter(&names)
+        .map(|name| registry.tag(name))
+        .buffered(REGISTRY_TAGS_JOBS);
+
+    while !collector.is_full() {
+        match tags.next().await {
+            Some(tag) => collector.push(tag?),
+            None => break,
+        }
+    }
+
+    Ok(count)
+}
+
+/// Collect tags until `limit` results are found.
+///
+/// Tags and images that don't match `filter` are removed, so they are not
+/// counted in `limit`. In the `Images` view, every image is counted as a
+/// result. In other views, every tag.
+///
+/// If the reference contains a tag, only that tag is accepted. If it
+/// contains a digest, only the images with that digest are accepted.
+struct Collector<'a> {
+    reference: &'a Reference,
+    filter: &'a Filter<'a>,
+    filters_images: bool,
+    view: View,
+    pending: usize,
+    tags: Vec<Tag>,
+}
+
+impl<'a> Collector<'a> {
+    fn new(reference: &'a Reference, filter: &'a Filter<'a>, view: View, limit: usize) -> Self {
+        Collector {
+            reference,
+            filter,
+            filters_images: filter.filters_images() || reference.digest.is_some(),
+            view,
+            pending: limit,
+            tags: Vec::new(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.pending == 0
+    }
+
+    fn push(&mut self, mut tag: Tag) {
+        if !self.filter.matches_tag(&tag)
+            || matches!(&self.reference.tag, Some(t) if *t != tag.name)
+        {
+            return;
+        }
+
+        let filter = self.filter;
+        let digest = &self.reference.digest;
+
+        tag.attestations = tag.images.iter().any(Image::is_attestation);
+        tag.images.retain(|image| {
+            filter.matches_image(image) && (digest.is_none() || image.digest == *digest)
+        });
+
+        if self.view == View::Images {
+            tag.images.truncate(self.pending);
+            self.pending -= tag.images.len();
+        } else if !tag.images.is_empty() || !self.filters_images {
+            self.pending -= 1;
+        }
+
+        if !tag.images.is_empty() || (self.view != View::Images && !self.filters_images) {
+            self.tags.push(tag);
+        }
+    }
+}
+
+/// Keep up to `limit` results in `tags`, and return the number of results.
+///
+/// Results are counted like in `Collector`.
+fn take_results(tags: &mut Vec<Tag>, view: View, limit: usize) -> usize {
+    if view != View::Images {
+        tags.truncate(limit);
+        return tags.len();
+    }
+
+    let mut results = 0;
+    let mut keep = 0;
+
+    for tag in tags.iter_mut() {
+        if results == limit {
+            break;
+        }
+
+        tag.images.truncate(limit - results);
+        results += tag.images.len();
+        keep += 1;
+    }
+
+    tags.truncate(keep);
+    results
+}
+
+/// Print every tag, followed by a line for every image.
+///
+/// The widths of the columns are computed with the images of all tags, so
+/// they are aligned in the whole repository.
+fn write_tree(output: &mut dyn Write, table: &mut Table, tags: &[Tag]) -> anyhow::Result<()> {
+    let rows: Vec<Vec<_>> = tags
+        .iter()
+        .map(|tag| {
+            tag.images
+                .iter()
+                .enumerate()
+                .map(|(index, image)| {
+                    let branch = if index + 1 == tag.images.len() {
+                        "└─"
+                    } else {
+                        "├─"
+                    };
+
+                    vec![
+                        format!("{} {}", branch, image.platform()),
+                        bytesize::to_string(image.size, true),
+                        image.digest.clone().unwrap_or_default(),
+                    ]
+                })
+                .collect()
+        })
+        .collect();
+
+    for row in rows.iter().flatten() {
+        table.push(row.clone());
+    }
+
+    let layout = table.layout(terminal_width(), false);
+    table.clear();
+
+    for (tag, rows) in tags.iter().zip(rows) {
+        write!(output, "{}", tag.name)?;
+
+        match (tag.last_updated, tag.attestations) {
+            (Some(lu), true) => writeln!(output, "  ({}, attestations)", lu.to_human())?,
+            (Some(lu), false) => writeln!(output, "  ({})", lu.to_human())?,
+            (None, true) => writeln!(output, "  (attestations)")?,
+            (None, false) => writeln!(output)?,
+        }
+
+        for row in rows {
+            table.push(row);
+        }
+
+        table.render_layout(output, &layout, false)?;
+        table.clear();
     }
 
     Ok(())
 }
+
+/// Group tags that point to the same images.
+///
+/// Two tags are aliases if they have the same digest for every platform.
+/// Tags without digests are never grouped.
+fn group_aliases(tags: &[Tag]) -> Vec<Vec<&Tag>> {
+    // Pairs of (platform, digest), sorted.
+    type Key<'a> = Vec<(String, &'a str)>;
+
+    let mut groups: Vec<Vec<&Tag>> = Vec::new();
+
+    // Position of the group for every key.
+    let mut positions: HashMap<Key, usize> = HashMap::new();
+
+    for tag in tags {
+        let mut key: Vec<_> = tag
+            .images
+            .iter()
+            .filter_map(|i| i.digest.as_deref().map(|d| (i.platform(), d)))
+            .collect();
+
+        key.sort();
+
+        if key.is_empty() {
+            groups.push(vec![tag]);
+            continue;
+        }
+
+        match positions.entry(key) {
+            Entry::Occupied(entry) => groups[*entry.get()].push(tag),
+            Entry::Vacant(entry) => {
+                entry.insert(groups.len());
+                groups.push(vec![tag]);
+            }
+        }
+    }
+
+    groups
+}
+
+/// Compact list of the platforms of the images.
+///
+/// The OS is omitted for Linux images.
+fn platforms(images: &[Image]) -> String {
+    let mut platforms: Vec<String> = Vec::new();
+
+    for image in images.iter().filter(|i| !i.is_attestation()) {
+        let platform = if image.os == "linux" {
+            image.architecture_variant()
+        } else {
+            image.platform()
+        };
+
+        if !platforms.contains(&platform) {
+            platforms.push(platform);
+        }
+    }
+
+    platforms.join(",")
+}
+
+/// Variant of the image, or the default variant of its architecture.
+fn image_variant(image: &Image) -> Option<&str> {
+    match image.variant.as_deref() {
+        Some(variant) if !variant.is_empty() => Some(variant),
+        _ => platform::default_variant(&image.architecture),
+    }
+}
+
+/// Value for the `attestations` column.
+///
+/// Docker Hub does not report the kind of the attestations (SBOM or
+/// provenance), only that they exist.
+fn attestations_label(tag: &Tag) -> String {
+    if tag.attestations {
+        "yes".to_string()
+    } else {
+        String::new()
+    }
+}
+
+/// Range of the sizes of the images.
+fn size_range(images: &[Image]) -> String {
+    let sizes = || {
+        images
+            .iter()
+            .filter(|i| !i.is_attestation())
+            .map(|i| i.size)
+    };
+    let min = sizes().min();
+    let max = sizes().max();
+
+    match (min, max) {
+        (Some(min), Some(max)) if min == max => bytesize::to_string(min, true),
+        (Some(min), Some(max)) => format!(
+            "{} - {}",
+            bytesize::to_string(min, true),
+            bytesize::to_string(max, true)
+        ),
+        _ => String::new(),
+    }
+}
+
+#[test]
+fn summarize_images() {
+    let image = |os: &str, arch: &str, variant: Option<&str>, size| Image {
+        architecture: arch.to_string(),
+        variant: variant.map(str::to_string),
+        os: os.to_string(),
+        os_version: None,
+        size,
+        digest: None,
+    };
+
+    let images = [
+        image("linux", "amd64", None, 30_000_000),
+        image("linux", "arm", Some("v7"), 20_000_000),
+        image("linux", "arm", Some("v7"), 20_000_000),
+        image("windows", "amd64", None, 90_000_000),
+        image("unknown", "unknown", None, 10_000),
+    ];
+
+    assert_eq!(platforms(&images), "amd64,arm/v7,windows/amd64");
+    assert_eq!(image_variant(&images[0]), None);
+    assert_eq!(image_variant(&images[1]), Some("v7"));
+    assert_eq!(image_variant(&image("linux", "arm64", None, 0)), Some("v8"));
+    assert_eq!(size_range(&images), "19.1 MiB - 85.8 MiB");
+    assert_eq!(size_range(&images[1..2]), "19.1 MiB");
+    assert_eq!(images[4].platform(), "attestation");
+    assert_eq!(size_range(&[]), "");
+}
+
+#[test]
+fn group_tags_by_digest() {
+    let tag = |name: &str, digests: &[(&str, &str)]| Tag {
+        name: name.to_string(),
+        last_updated: None,
+        attestations: false,
+        images: digests
+            .iter()
+            .map(|(arch, digest)| Image {
+                architecture: arch.to_string(),
+                variant: None,
+                os: "linux".to_string(),
+                os_version: None,
+                size: 0,
+                digest: Some(digest.to_string()),
+            })
+            .collect(),
+    };
+
+    let tags = [
+        tag("latest", &[("amd64", "a1"), ("arm64", "b1")]),
+        tag("3.12", &[("amd64", "a2"), ("arm64", "b2")]),
+        tag("3.12.1", &[("arm64", "b1"), ("amd64", "a1")]),
+        tag("3", &[("amd64", "a1"), ("arm64", "b1")]),
+        tag("slim", &[("amd64", "a1")]),
+        tag("empty", &[]),
+        tag("other", &[]),
+    ];
+
+    let groups: Vec<Vec<_>> = group_aliases(&tags)
+        .iter()
+        .map(|g| g.iter().map(|t| t.name.as_str()).collect())
+        .collect();
+
+    assert_eq!(
+        groups,
+        [
+            vec!["latest", "3.12.1", "3"],
+            vec!["3.12"],
+            vec!["slim"],
+            vec!["empty"],
+            vec!["other"],
+        ]
+    );
+}
+
+#[test]
+fn show_tags_from_fixtures() {
+    use assert_cmd::prelude::*;
+    use std::process::Command;
+
+    let tags = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args([
+            "--no-pager",
+            "--replay",
+            crate::backend::TEST_FIXTURES,
+            "tags",
+        ]);
+        cmd.args(args);
+        let process = cmd.unwrap();
+        assert!(process.status.success());
+        String::from_utf8(process.stdout).unwrap()
+    };
+
+    let output = tags(&[
+        "--columns",
+        "name,platforms,size,attestations",
+        "-g",
+        "redis",
+    ]);
+    let lines: Vec<_> = output.lines().map(str::trim_end).collect();
+    assert_eq!(
+        lines,
+        [
+            "- 3 results for redis",
+            "NAME    PLATFORMS                    SIZE  ATTESTATIONS",
+            "latest  amd64,arm/v7  31.5 MiB - 36.4 MiB  yes",
+            "7       amd64,arm/v7  31.5 MiB - 36.4 MiB",
+            "6       amd64                    34.3 MiB",
+        ]
+    );
+
+    let output = tags(&[
+        "--columns",
+        "name,arch",
+        "--platform",
+        "linux/arm",
+        "redis:7",
+    ]);
+    let lines: Vec<_> = output.lines().map(str::trim_end).collect();
+    assert_eq!(
+        lines,
+        ["- 1 results for redis:7", "NAME  ARCH", "7     arm/v7"]
+    );
+
+    let output = tags(&[
+        "-g",
+        "--columns",
+        "name",
+        "--total-limit",
+        "2",
+        "redis",
+        "httpd",
+    ]);
+    let lines: Vec<_> = output.lines().map(str::trim_end).collect();
+    assert_eq!(lines, ["- 3 results for redis", "NAME", "latest", "7"]);
+
+    let output = tags(&["--tree", "--columns", "platform,size", "redis"]);
+    let lines: Vec<_> = output.lines().map(str::trim_end).collect();
+    assert_eq!(
+        lines,
+        [
+            "- 3 results for redis",
+            "latest  (2020-12-11, attestations)",
+            "├─ linux/amd64   36.4 MiB",
+            "└─ linux/arm/v7  31.5 MiB",
+            "7  (2020-12-11)",
+            "├─ linux/amd64   36.4 MiB",
+            "└─ linux/arm/v7  31.5 MiB",
+            "6  (2020-10-01)",
+            "└─ linux/amd64   34.3 MiB",
+        ]
+    );
+
+    let process = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--no-pager", "--output", "json"])
+        .args(["--replay", crate::backend::TEST_FIXTURES, "tags"])
+        .args(["--limit", "2", "redis"])
+        .unwrap();
+    let tags: Vec<serde_json::Value> = String::from_utf8(process.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let names: Vec<_> = tags
+        .iter()
+        .map(|t| (t["repository"].as_str(), t["name"].as_str()))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            (Some("library/redis"), Some("latest")),
+            (Some("library/redis"), Some("7"))
+        ]
+    );
+    assert_eq!(tags[0]["attestations"], true);
+
+    let process = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--replay", crate::backend::TEST_FIXTURES, "tags"])
+        .args(["--platform", "linux/arm", "-a", "amd64", "redis"])
+        .output()
+        .unwrap();
+    assert!(!process.status.success());
+}
+
+#[test]
+fn stop_fetching_hub_pages() {
+    use std::sync::Mutex;
+
+    use chrono::TimeZone;
+
+    use crate::backend::Response;
+
+    /// Pages with one tag, pushed one day before the tag in the previous
+    /// page.
+    struct Pages {
+        requests: Mutex<usize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Backend for Pages {
+        async fn get(&self, url: &str, _: &[(&str, &str)]) -> anyhow::Result<Response> {
+            let mut requests = self.requests.lock().unwrap();
+            *requests += 1;
+
+            let page = *requests;
+            let body = serde_json::json!({
+                "count": 20,
+                "next": if page < 20 { Some("https://hub.docker.com/next") } else { None },
+                "results": [{
+                    "name": format!("tag{}", page),
+                    "last_updated": Utc.timestamp_opt(1_600_000_000 - page as i64 * 86400, 0).unwrap(),
+                    "images": [],
+                }],
+            });
+
+            Ok(Response {
+                url: url.to_string(),
+                status: 200,
+                headers: Vec::new(),
+                body: serde_json::to_vec(&body)?,
+            })
+        }
+    }
+
+    let fetch = |reference: &str, filter: &Filter| {
+        let pages = Arc::new(Pages {
+            requests: Mutex::new(0),
+        });
+
+        let reference = reference.parse().unwrap();
+        let backend: Arc<dyn Backend> = pages.clone();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (count, tags) = rt
+            .block_on(fetch_tags(&backend, &reference, 1, filter, View::Group, 30))
+            .unwrap();
+
+        let names: Vec<_> = tags.into_iter().map(|t| t.name).collect();
+        let requests = *pages.requests.lock().unwrap();
+        (count, names, requests)
+    };
+
+    let mut filter = Filter {
+        os: None,
+        arch: None,
+        variant: None,
+        attestations: false,
+        since: Utc.timestamp_opt(1_600_000_000 - 3 * 86400, 0).single(),
+        before: None,
+        name_match: None,
+        exclude: None,
+        min_size: None,
+        max_size: None,
+    };
+
+    // The background task downloads a few pages in advance.
+    let (_, names, requests) = fetch("foo", &filter);
+    assert_eq!(names, ["tag1", "tag2", "tag3"]);
+    assert!(requests < 10, "{} requests", requests);
+
+    filter.since = None;
+    let (_, names, requests) = fetch("foo", &filter);
+    assert_eq!(names.len(), 20);
+    assert_eq!(requests, 20);
+
+    let (count, names, requests) = fetch("foo:tag5", &filter);
+    assert_eq!((count, names), (1, vec!["tag5".to_string()]));
+    assert!(requests < 10, "{} requests", requests);
+}
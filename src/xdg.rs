@@ -0,0 +1,46 @@
+//! Locations for the files managed by hdcquery, following the [XDG Base
+//! Directory Specification].
+//!
+//! [XDG Base Directory Specification]: https://specifications.freedesktop.org/basedir-spec/latest/
+
+use std::env;
+use std::path::PathBuf;
+
+const APP_DIR: &str = "hdcquery";
+
+/// Directory to store persistent data, like the history of the prompt.
+///
+/// It is `$XDG_STATE_HOME/hdcquery`, or `$HOME/.local/state/hdcquery` if
+/// the variable is not defined.
+pub fn state_home() -> Option<PathBuf> {
+    base_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// Directory for configuration files.
+///
+/// It is `$XDG_CONFIG_HOME/hdcquery`, or `$HOME/.config/hdcquery` if the
+/// variable is not defined.
+pub fn config_home() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Directory for cached data, like the responses saved with `--cache-ttl`.
+///
+/// It is `$XDG_CACHE_HOME/hdcquery`, or `$HOME/.cache/hdcquery` if the
+/// variable is not defined.
+pub fn cache_home() -> Option<PathBuf> {
+    base_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// Compute the path for a base directory.
+///
+/// According to the specification, relative paths in the environment
+/// variables are ignored.
+fn base_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = match env::var_os(var).map(PathBuf::from) {
+        Some(path) if path.is_absolute() => path,
+        _ => PathBuf::from(env::var_os("HOME")?).join(fallback),
+    };
+
+    Some(base.join(APP_DIR))
+}